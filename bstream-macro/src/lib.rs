@@ -1,57 +1,187 @@
+use std::collections::HashMap;
+
 use proc_macro::TokenStream;
+use proc_macro2::{Group, TokenTree};
 
-use syn::{Data, DeriveInput, Field, Ident, Meta, parse_macro_input};
+use syn::{Data, DeriveInput, Field, Ident, Meta, parse_macro_input, Type};
 use syn::__private::quote::__private::Span;
 use syn::__private::quote::quote;
 use syn::__private::ToTokens;
-use syn::Type::Path;
+use syn::ext::IdentExt;
 
 #[proc_macro_attribute]
 pub fn b_enum(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as DeriveInput);
-    if let Data::Enum(syn::DataEnum { variants, .. }) = &input.data {
-        let ls = attr.to_string();
-        let typ_str: Vec<&str> = ls.splitn(2, ',').collect();
-        let mut little_endian = true;
-        let mut varint = false;
-        if typ_str.len() > 1 {
-            little_endian = typ_str[1].trim().to_lowercase() != ("bigendian");
-            varint = typ_str[1].trim().to_lowercase() == ("varint");
-        }
-        let typ = Ident::new(typ_str[0], Span::call_site());
-        let rf = gen_fn(typ_str[0], little_endian, true, varint);
-        let wf = gen_fn(typ_str[0], little_endian, false, varint);
-        let enum_id = &input.ident;
+    let mut input = parse_macro_input!(item as DeriveInput);
+    let enum_id = input.ident.clone();
+    let (typ, little_endian, varint) = match parse_b_enum_attr(attr) {
+        Ok(v) => v,
+        Err(e) => {
+            let err = e.to_compile_error();
+            return quote! {#err #input}.into();
+        }
+    };
+    if let Data::Enum(syn::DataEnum { variants, .. }) = &mut input.data {
+        let mut typ_str = typ.to_string();
+        if varint {
+            typ_str = varint_typ(&typ_str);
+        }
+        let rf = gen_fn(&typ_str, little_endian, true);
+        let wf = gen_fn(&typ_str, little_endian, false);
         let mut read = quote! {};
+        let mut write = quote! {};
+        let mut unknown = None;
 
-        for variant in variants {
+        for variant in variants.iter_mut() {
             let v_id = &variant.ident;
+            let len = variant.attrs.len();
+            variant
+                .attrs
+                .retain(|attr| !attr.path().is_ident("unknown"));
+            if variant.attrs.len() != len {
+                if unknown.is_some() {
+                    panic!("{} has more than one #[unknown] variant", enum_id);
+                }
+                let syn::Fields::Unnamed(fields) = &variant.fields else {
+                    panic!("the #[unknown] variant must hold the raw value, such as Unknown(u64)");
+                };
+                let raw = &fields.unnamed.first().unwrap().ty;
+                if typ != raw.to_token_stream().to_string() {
+                    panic!(
+                        "the #[unknown] variant must hold the discriminant type, such as Unknown({})",
+                        typ
+                    );
+                }
+                write.extend(quote! {
+                    #enum_id::#v_id(v) => *v,
+                });
+                unknown = Some(v_id.clone());
+                continue;
+            }
             let v_v = variant.discriminant.clone().unwrap().1;
             read.extend(quote! {
-                #v_v => ::std::io::Result::Ok(#enum_id::#v_id),
-            })
+                #v_v => ::bstream::Result::Ok(#enum_id::#v_id),
+            });
+            write.extend(quote! {
+                #enum_id::#v_id => #v_v,
+            });
+        }
+        let enum_name = enum_id.to_string();
+        let encoded_len = if varint {
+            let len = gen_len(&typ_str, quote! {v});
+            quote! {
+                let v: #typ = match self {
+                    #write
+                };
+                #len
+            }
+        } else {
+            quote! {::std::mem::size_of::<#typ>()}
+        };
+        // the catch-all arm goes last, wherever the #[unknown] variant is declared, so that it does not shadow
+        // the discriminants of the variants after it.
+        match &unknown {
+            Some(v_id) => {
+                read.extend(quote! {
+                    v => ::bstream::Result::Ok(#enum_id::#v_id(v)),
+                });
+                if !input.attrs.iter().any(|attr| attr.path().is_ident("repr")) {
+                    // explicit discriminants next to a variant with fields need a primitive representation.
+                    input.attrs.push(syn::parse_quote!(#[repr(#typ)]));
+                }
+            }
+            None => read.extend(quote! {
+                v => {
+                    ::bstream::Result::Err(::bstream::Error::new(::bstream::ErrorKind::InvalidDiscriminant(v as i128)).in_type(#enum_name))
+                },
+            }),
         }
         return quote! {
             #input
-            impl ::bstream::EnumBinaryStream for #enum_id {
-                fn read(out: &mut impl ::std::io::Read) -> ::std::io::Result<Self> {
+            impl ::bstream::Decode for #enum_id {
+                fn decode(out: &mut impl ::std::io::Read) -> ::bstream::Result<Self> {
                     match #rf(out)? {
                         #read
-                        v => {
-                            ::std::io::Result::Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, format!("invalid value {}", v)))
-                        },
                     }
                 }
 
-                fn write(&self, out: &mut impl ::std::io::Write) -> ::std::io::Result<()> {
-                    #wf(out, self.clone() as #typ)
+                fn skip(out: &mut impl ::std::io::Read) -> ::bstream::Result<()> {
+                    #rf(out)?;
+                    ::bstream::Result::Ok(())
                 }
             }
-        }.into();
+
+            impl ::bstream::Encode for #enum_id {
+                fn encode(&self, out: &mut impl ::std::io::Write) -> ::bstream::Result<()> {
+                    let v: #typ = match self {
+                        #write
+                    };
+                    ::bstream::Result::Ok(#wf(out, v)?)
+                }
+
+                fn encoded_len(&self) -> usize {
+                    #encoded_len
+                }
+            }
+        }
+        .into();
     }
     unimplemented!()
 }
 
+/// parse_b_enum_attr parses the `#[b_enum(type, options...)]` attribute into the discriminant type and
+/// whether it is little endian and a varint.
+fn parse_b_enum_attr(attr: TokenStream) -> syn::Result<(Ident, bool, bool)> {
+    let args = syn::parse::Parser::parse(
+        syn::punctuated::Punctuated::<Ident, syn::Token![,]>::parse_terminated,
+        attr,
+    )?;
+    let mut args = args.into_iter();
+    let typ = args.next().ok_or_else(|| {
+        syn::Error::new(
+            Span::call_site(),
+            "b_enum needs a discriminant type, such as #[b_enum(u8)]",
+        )
+    })?;
+    let typ_str = typ.to_string();
+    if !is_supported_typ(&typ_str) || matches!(typ_str.as_str(), "bool" | "f32" | "f64") {
+        return Err(syn::Error::new(
+            typ.span(),
+            "b_enum discriminants must be integers",
+        ));
+    }
+    let mut endian: Option<Ident> = None;
+    let mut varint: Option<Ident> = None;
+    for arg in args {
+        let slot = match arg.to_string().as_str() {
+            "LittleEndian" | "BigEndian" => &mut endian,
+            "Varint" => &mut varint,
+            _ => {
+                return Err(syn::Error::new(
+                    arg.span(),
+                    "expected LittleEndian, BigEndian or Varint",
+                ))
+            }
+        };
+        if slot.is_some() {
+            return Err(syn::Error::new(arg.span(), "conflicting b_enum option"));
+        }
+        *slot = Some(arg);
+    }
+    if let Some(varint) = &varint {
+        if !matches!(typ_str.as_str(), "u32" | "u64" | "i32" | "i64") {
+            return Err(syn::Error::new(
+                varint.span(),
+                "Varint is only supported on u32, u64, i32 and i64",
+            ));
+        }
+        if let Some(endian) = &endian {
+            return Err(syn::Error::new(endian.span(), "varints have no byte order"));
+        }
+    }
+    let little_endian = endian.is_none_or(|e| e != "BigEndian");
+    Ok((typ, little_endian, varint.is_some()))
+}
+
 #[inline]
 fn is_supported_typ(str: &str) -> bool {
     matches!(
@@ -60,60 +190,602 @@ fn is_supported_typ(str: &str) -> bool {
     )
 }
 
-#[proc_macro_derive(BStream, attributes(LittleEndian, BigEndian, Varint))]
+#[proc_macro_derive(
+    BStream,
+    attributes(
+        LittleEndian,
+        BigEndian,
+        Varint,
+        U16Len,
+        U32Len,
+        I32Len,
+        max_len,
+        since,
+        until,
+        bstream
+    )
+)]
 pub fn derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let struct_identifier = &input.ident;
+    derive_input(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
 
-    match input.data {
+/// derive_input returns the Decode and Encode impls of a struct or data-carrying enum, or the error of the
+/// first attribute that cannot be used, spanned to the attribute.
+fn derive_input(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let identifier = &input.ident;
+    let name = identifier.to_string();
+
+    match &input.data {
         Data::Struct(syn::DataStruct { fields, .. }) => {
+            let FieldStmts {
+                read,
+                write,
+                len,
+                skip,
+                construct,
+            } = derive_fields(&name, fields, true, |i, field| match &field.ident {
+                Some(id) => quote! {self.#id},
+                None => {
+                    let i = syn::Index::from(i);
+                    quote! {self.#i}
+                }
+            })?;
+            let skip = skip.map(|skip| {
+                quote! {
+                    fn skip(out: &mut impl ::std::io::Read) -> ::bstream::Result<()> {
+                        #skip
+                        ::bstream::Result::Ok(())
+                    }
+                }
+            });
+
+            Ok(quote! {
+                impl ::bstream::Decode for #identifier {
+                    fn decode(out: &mut impl ::std::io::Read) -> ::bstream::Result<Self> {
+                        #read
+                        ::bstream::Result::Ok(Self #construct)
+                    }
+
+                    #skip
+                }
+
+                impl ::bstream::Encode for #identifier {
+                    fn encode(&self, out: &mut impl ::std::io::Write) -> ::bstream::Result<()> {
+                        #write
+                        ::bstream::Result::Ok(())
+                    }
+
+                    fn encoded_len(&self) -> usize {
+                        let mut __encoded_len = 0usize;
+                        #len
+                        __encoded_len
+                    }
+                }
+            })
+        }
+        Data::Enum(syn::DataEnum { variants, .. }) => {
+            let tag = tag_attrs(&input.attrs)?.tag.ok_or_else(|| {
+                syn::Error::new(
+                    identifier.span(),
+                    "data-carrying enums need a #[bstream(tag = ...)] attribute",
+                )
+            })?;
+            let tag_span = tag.span();
+            let tag = tag.to_string();
+            // the tag is read like a field of the named type, where varuN and variN are varints.
+            let tag_typ = match tag.as_str() {
+                "varu32" => "vu32".to_string(),
+                "varu64" => "vu64".to_string(),
+                "vari32" => "var_i32".to_string(),
+                "vari64" => "var_i64".to_string(),
+                typ if is_supported_typ(typ) && typ != "bool" && !typ.starts_with('f') => {
+                    typ.to_string()
+                }
+                typ => {
+                    return Err(syn::Error::new(
+                        tag_span,
+                        format!("unsupported tag type {}", typ),
+                    ))
+                }
+            };
+            let tag_rf = gen_fn(&tag_typ, true, true);
+            let tag_wf = gen_fn(&tag_typ, true, false);
             let mut read = quote! {};
             let mut write = quote! {};
+            let mut len = quote! {};
+            // skip stays Some as long as no variant has to be decoded to be skipped.
+            let mut skip = Some(quote! {});
+            for variant in variants {
+                let v_id = &variant.ident;
+                let tag_value = tag_attrs(&variant.attrs)?.tag_value.ok_or_else(|| {
+                    syn::Error::new(
+                        v_id.span(),
+                        format!("{} needs a #[bstream(tag_value = ...)] attribute", v_id),
+                    )
+                })?;
+                let variant_name = format!("{}::{}", name, v_id);
+                // fields are bound by reference, so that (*binding) is a place like self.field is for structs.
+                let FieldStmts {
+                    read: read_fields,
+                    write: write_fields,
+                    len: len_fields,
+                    skip: skip_fields,
+                    construct,
+                } = derive_fields(&variant_name, &variant.fields, false, |i, field| {
+                    let binding = local(i, field);
+                    quote! {(*#binding)}
+                })?;
+                let pattern = quote! {#identifier::#v_id #construct};
+                skip = skip.zip(skip_fields).map(|(mut skip, skip_fields)| {
+                    skip.extend(quote! {
+                        #tag_value => {
+                            #skip_fields
+                        }
+                    });
+                    skip
+                });
+                read.extend(quote! {
+                    #tag_value => {
+                        #read_fields
+                        #pattern
+                    }
+                });
+                write.extend(quote! {
+                    #pattern => {
+                        #tag_wf(out, #tag_value)?;
+                        #write_fields
+                    }
+                });
+                let tag_len = gen_len(&tag_typ, quote! {#tag_value});
+                len.extend(quote! {
+                    #pattern => {
+                        __encoded_len += #tag_len;
+                        #len_fields
+                    }
+                });
+            }
 
-            for field in fields {
-                match &field.ty {
-                    Path(_) => {
-                        let typ_str = field.ty.to_token_stream().to_string();
-                        if !is_supported_typ(&typ_str) {
-                            let field_id = &field.ident;
-                            let typ = &field.ty;
-                            read.extend(quote! {
-                                self.#field_id = #typ::read(out)?;
-                            });
-                            write.extend(quote! {
-                                self.#field_id.write(out)?;
-                            });
-                            continue;
+            let invalid = quote! {
+                v => {
+                    return ::bstream::Result::Err(::bstream::Error::new(::bstream::ErrorKind::InvalidDiscriminant(v as i128)).in_type(#name));
+                }
+            };
+            let skip = skip.map(|skip| {
+                quote! {
+                    fn skip(out: &mut impl ::std::io::Read) -> ::bstream::Result<()> {
+                        match #tag_rf(out).map_err(|e| ::bstream::Error::from(e).in_type(#name))? {
+                            #skip
+                            #invalid
                         }
-                        let mut little_endian = true;
-                        let mut varint = false;
-                        scan_attribute(&field, &mut little_endian, &mut varint);
-                        read.extend(get_func(&field, little_endian, varint, true));
-                        write.extend(get_func(&field, little_endian, varint, false));
+                        ::bstream::Result::Ok(())
                     }
-                    _ => unimplemented!(
-                        "unsupported type {}",
-                        field.ty.to_token_stream().to_string()
-                    ),
                 }
-            }
+            });
 
-            quote! {
-                impl bstream::BinaryStream for #struct_identifier {
-                    fn read(&mut self, out: &mut impl ::std::io::Read) -> ::std::io::Result<()> {
-                        #read
-                        ::std::io::Result::Ok(())
+            Ok(quote! {
+                impl ::bstream::Decode for #identifier {
+                    fn decode(out: &mut impl ::std::io::Read) -> ::bstream::Result<Self> {
+                        ::bstream::Result::Ok(match #tag_rf(out).map_err(|e| ::bstream::Error::from(e).in_type(#name))? {
+                            #read
+                            #invalid
+                        })
                     }
 
-                    fn write(&self, out: &mut impl ::std::io::Write) -> ::std::io::Result<()> {
-                        #write
-                        ::std::io::Result::Ok(())
+                    #skip
+                }
+
+                impl ::bstream::Encode for #identifier {
+                    #[allow(unused_variables)]
+                    fn encode(&self, out: &mut impl ::std::io::Write) -> ::bstream::Result<()> {
+                        match self {
+                            #write
+                        }
+                        ::bstream::Result::Ok(())
+                    }
+
+                    #[allow(unused_variables)]
+                    fn encoded_len(&self) -> usize {
+                        let mut __encoded_len = 0usize;
+                        match self {
+                            #len
+                        }
+                        __encoded_len
                     }
                 }
+            })
+        }
+        Data::Union(_) => Err(syn::Error::new(
+            identifier.span(),
+            "BStream cannot be derived for unions",
+        )),
+    }
+}
+
+/// FieldStmts holds the statements derive_fields generates for the fields of a struct or an enum variant.
+struct FieldStmts {
+    read: proc_macro2::TokenStream,
+    write: proc_macro2::TokenStream,
+    /// len adds the encoded length of the fields to `__encoded_len`, which is named so as not to clash with
+    /// the fields bound by enum variants.
+    len: proc_macro2::TokenStream,
+    /// skip advances past the fields without decoding them. It is None if a field depends on the value of
+    /// another one, through a condition or len_of, in which case the fields have to be decoded instead.
+    skip: Option<proc_macro2::TokenStream>,
+    /// construct lists the local variables the fields are decoded into the way the fields are declared, such
+    /// as `{ a: __field_a, b: __field_b }` or `(__field_0, __field_1)`, so that it completes a constructor or
+    /// a pattern.
+    construct: proc_macro2::TokenStream,
+}
+
+/// derive_fields returns the statements that handle fields one after another. Fields are decoded into local
+/// variables named by [local], and encoded from the place expression returned by place, such as
+/// `self.name`. Conditions are only allowed if conditional is set, since they refer to the fields through
+/// `self`. Attributes that cannot be used on a field are returned as errors spanned to the attribute.
+fn derive_fields(
+    type_name: &str,
+    fields: &syn::Fields,
+    conditional: bool,
+    place: impl Fn(usize, &Field) -> proc_macro2::TokenStream,
+) -> syn::Result<FieldStmts> {
+    let mut read = quote! {};
+    let mut write = quote! {};
+    let mut len = quote! {};
+    let mut skip = Some(quote! {});
+    let locals: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| local(i, f))
+        .collect();
+    let places: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| place(i, f))
+        .collect();
+    let names: Vec<String> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match &f.ident {
+            Some(id) => id.to_string(),
+            None => i.to_string(),
+        })
+        .collect();
+    // len_of maps the name of a field to the index and len_of attribute of the field holding its length.
+    let mut len_of = HashMap::new();
+    for (i, field) in fields.iter().enumerate() {
+        if let Some(target) = field_attrs(field)?.len_of {
+            if len_of.contains_key(&target.to_string()) {
+                return Err(syn::Error::new(
+                    target.span(),
+                    format!("{} has more than one len_of field", target),
+                ));
             }
-            .into()
+            len_of.insert(target.to_string(), (i, target));
         }
-        _ => unimplemented!(),
+    }
+    for (i, field) in fields.iter().enumerate() {
+        let place = &places[i];
+        let local = &locals[i];
+        let typ_str = field.ty.to_token_stream().to_string();
+        let ctx = field_context(type_name, &names[i]);
+        let prefix = len_prefix(field);
+        let attrs = field_attrs(field)?;
+        if let Some(target) = &attrs.len_of {
+            if !len_of.contains_key(&target.to_string()) {
+                return Err(syn::Error::new(
+                    target.span(),
+                    format!("len_of field of {} must come before it", target),
+                ));
+            }
+        }
+        let len_field = len_of.remove(&names[i]).map(|(n, _)| n);
+        let mut write_stmt = quote! {};
+        let len_expr;
+        // skip_expr is None if the field cannot be skipped without knowing the values of other fields.
+        let mut skip_expr = None;
+        let mut read_expr = if let Some(n) = len_field {
+            if let Some((_, attr)) = prefix {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    format!(
+                        "{} has its length in {}, so it cannot have a length prefix",
+                        names[i], names[n]
+                    ),
+                ));
+            }
+            let len_field = &locals[n];
+            if typ_str == "String" {
+                write_stmt.extend(quote! {
+                    ::std::io::Write::write_all(out, #place.as_bytes())?;
+                });
+                len_expr = quote! {#place.len()};
+                quote! {::bstream::read_str(out, #len_field as u64)}
+            } else if is_vec(&field.ty) {
+                write_stmt.extend(quote! {
+                    ::bstream::write_seq(out, &#place)?;
+                });
+                len_expr = quote! {::bstream::seq_len(&#place)};
+                quote! {::bstream::read_seq(out, #len_field as u64)}
+            } else {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "len_of is only supported on String and Vec fields",
+                ));
+            }
+        } else if typ_str == "String" {
+            let suffix = match prefix {
+                None => "",
+                Some(("U16", _)) => "_u16",
+                Some(("I32", _)) => "_i32",
+                Some((p, attr)) => {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        format!("{}Len is not supported on String fields", p),
+                    ))
+                }
+            };
+            let prefix = Ident::new(prefix.map_or("Vu32", |(p, _)| p), Span::call_site());
+            len_expr =
+                quote! {::bstream::LenPrefix::#prefix.encoded_len(#place.len()) + #place.len()};
+            skip_expr = Some(quote! {
+                ::bstream::LenPrefix::#prefix
+                    .read(out)
+                    .and_then(|len| ::bstream::skip_bytes(out, len))
+            });
+            let rf = Ident::new(&format!("read_string{}", suffix), Span::call_site());
+            let wf = Ident::new(&format!("write_string{}", suffix), Span::call_site());
+            write_stmt.extend(quote! {
+                ::bstream::WriterExt::#wf(out, &#place)?;
+            });
+            quote! {::bstream::ReaderExt::#rf(out)}
+        } else if let Some((prefix, attr)) = prefix {
+            if !is_vec(&field.ty) {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    format!("{}Len is only supported on String and Vec fields", prefix),
+                ));
+            }
+            let prefix = Ident::new(prefix, Span::call_site());
+            write_stmt.extend(quote! {
+                ::bstream::LenPrefix::#prefix.write(out, #place.len())?;
+                ::bstream::write_seq(out, &#place)?;
+            });
+            len_expr = quote! {::bstream::LenPrefix::#prefix.encoded_len(#place.len()) + ::bstream::seq_len(&#place)};
+            let elem = vec_elem(&field.ty)?;
+            skip_expr = Some(quote! {
+                ::bstream::LenPrefix::#prefix
+                    .read(out)
+                    .and_then(|len| ::bstream::skip_seq::<#elem>(out, len))
+            });
+            quote! {
+                ::bstream::LenPrefix::#prefix
+                    .read(out)
+                    .and_then(|len| ::bstream::read_seq(out, len))
+            }
+        } else if is_supported_typ(&typ_str) {
+            let mut little_endian = true;
+            let mut varint = false;
+            scan_attribute(field, &mut little_endian, &mut varint);
+            let typ = &field.ty;
+            let mut typ_str = typ_str.clone();
+            if varint {
+                typ_str = varint_typ(&typ_str);
+            }
+            let value = match &attrs.len_of {
+                Some(target) => {
+                    let target = match names.iter().position(|n| target == n) {
+                        Some(i) => &places[i],
+                        None => {
+                            return Err(syn::Error::new(
+                                target.span(),
+                                format!("len_of refers to {}, which is not a field", target),
+                            ))
+                        }
+                    };
+                    len_expr = gen_len(&typ_str, quote! {#target.len() as #typ});
+                    quote! {
+                        <#typ>::try_from(#target.len()).map_err(|_| {
+                            ::bstream::Error::new(::bstream::ErrorKind::LengthLimitExceeded {
+                                len: #target.len() as u64,
+                                limit: <#typ>::MAX as u64,
+                            })
+                        })?
+                    }
+                }
+                None => {
+                    len_expr = gen_len(&typ_str, quote! {#place});
+                    quote! {#place}
+                }
+            };
+            write_stmt.extend(get_func(field, little_endian, varint, false, value));
+            let read_expr = get_func(field, little_endian, varint, true, quote! {});
+            if attrs.len_of.is_none() {
+                skip_expr = Some(quote! {#read_expr.map(drop)});
+            }
+            read_expr
+        } else {
+            if let Some(target) = &attrs.len_of {
+                return Err(syn::Error::new(
+                    target.span(),
+                    "len_of is only supported on integer fields",
+                ));
+            }
+            // the encoding attributes only apply to the types is_supported_typ knows by name, so they would be
+            // silently ignored on aliases and containers.
+            if let Some(attr) = field.attrs.iter().find(|attr| {
+                ["LittleEndian", "BigEndian", "Varint"]
+                    .into_iter()
+                    .any(|name| attr.path().is_ident(name))
+            }) {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    format!(
+                        "{} is not supported on {}, use a wrapper such as bstream::VarU32 or bstream::BigEndian<T> instead",
+                        attr.path().to_token_stream(),
+                        typ_str
+                    ),
+                ));
+            }
+            write_stmt.extend(quote! {
+                ::bstream::Encode::encode(&#place, out)?;
+            });
+            len_expr = quote! {::bstream::Encode::encoded_len(&#place)};
+            let typ = &field.ty;
+            skip_expr = Some(quote! {<#typ as ::bstream::Decode>::skip(out)});
+            quote! {<#typ as ::bstream::Decode>::decode(out)}
+        };
+        if let Some(limit) = scan_max_len(field)? {
+            // the limit of the field can only tighten the limit of the context, which may have been lowered
+            // for an untrusted peer.
+            let context = quote! {
+                ::bstream::Context {
+                    max_len: ::std::cmp::min(#limit, ::bstream::Context::current().max_len),
+                    ..::bstream::Context::current()
+                }
+            };
+            read_expr = quote! {#context.scope(|| #read_expr)};
+            skip_expr = skip_expr.map(|skip_expr| quote! {#context.scope(|| #skip_expr)});
+        }
+        if let (Some(cond), false) = (&attrs.cond, conditional) {
+            return Err(syn::Error::new_spanned(
+                cond,
+                "conditions are only supported on struct fields",
+            ));
+        }
+        let version = version_cond(field)?;
+        if attrs.cond.is_some() {
+            skip = None;
+        }
+        let read_cond = and(
+            version.clone(),
+            attrs
+                .cond
+                .as_ref()
+                .map(|cond| decode_cond(cond.to_token_stream())),
+        );
+        let write_cond = and(
+            version.clone(),
+            attrs.cond.map(|cond| cond.to_token_stream()),
+        );
+        match read_cond.zip(write_cond) {
+            Some((read_cond, write_cond)) => {
+                skip = skip.zip(skip_expr).map(|(mut skip, skip_expr)| {
+                    // only a version condition is left here, which does not depend on other fields.
+                    skip.extend(quote! {
+                        if #read_cond {
+                            #skip_expr #ctx?;
+                        }
+                    });
+                    skip
+                });
+                read.extend(quote! {
+                    let #local = if #read_cond {
+                        #read_expr #ctx?
+                    } else {
+                        ::std::default::Default::default()
+                    };
+                });
+                write.extend(quote! {
+                    if #write_cond {
+                        #write_stmt
+                    }
+                });
+                len.extend(quote! {
+                    if #write_cond {
+                        __encoded_len += #len_expr;
+                    }
+                });
+            }
+            None => {
+                skip = skip.zip(skip_expr).map(|(mut skip, skip_expr)| {
+                    skip.extend(quote! {
+                        #skip_expr #ctx?;
+                    });
+                    skip
+                });
+                read.extend(quote! {
+                    let #local = #read_expr #ctx?;
+                });
+                write.extend(write_stmt);
+                len.extend(quote! {
+                    __encoded_len += #len_expr;
+                });
+            }
+        }
+    }
+    if let Some((_, target)) = len_of.into_values().next() {
+        return Err(syn::Error::new(
+            target.span(),
+            format!("len_of refers to {}, which is not a field", target),
+        ));
+    }
+    let construct = match fields {
+        syn::Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| &f.ident);
+            quote! {{ #(#idents: #locals),* }}
+        }
+        syn::Fields::Unnamed(_) => quote! {(#(#locals),*)},
+        syn::Fields::Unit => quote! {},
+    };
+    Ok(FieldStmts {
+        read,
+        write,
+        len,
+        skip,
+        construct,
+    })
+}
+
+/// local returns the local variable a field is decoded into, which is __field_ followed by the name of the
+/// field, or by N for the Nth field of a tuple. The prefix keeps a field from shadowing the reader `out`, or
+/// any other name the generated code relies on.
+fn local(i: usize, field: &Field) -> Ident {
+    match &field.ident {
+        Some(id) => Ident::new(&format!("__field_{}", id.unraw()), Span::call_site()),
+        None => Ident::new(&format!("__field_{}", i), Span::call_site()),
+    }
+}
+
+/// decode_cond rewrites the `self.field` references of a condition to the local variables the fields are
+/// decoded into, since there is no self yet while decoding.
+fn decode_cond(cond: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let tokens: Vec<_> = cond.into_iter().collect();
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        match (&tokens[i], tokens.get(i + 1), tokens.get(i + 2)) {
+            (
+                TokenTree::Ident(this),
+                Some(TokenTree::Punct(dot)),
+                Some(field @ (TokenTree::Ident(_) | TokenTree::Literal(_))),
+            ) if this == "self" && dot.as_char() == '.' => {
+                let name = field.to_string();
+                let local = Ident::new(
+                    &format!("__field_{}", name.trim_start_matches("r#")),
+                    field.span(),
+                );
+                out.push(TokenTree::Ident(local));
+                i += 3;
+                continue;
+            }
+            (TokenTree::Group(group), _, _) => {
+                let mut rewritten = Group::new(group.delimiter(), decode_cond(group.stream()));
+                rewritten.set_span(group.span());
+                out.push(TokenTree::Group(rewritten));
+            }
+            (token, _, _) => out.push(token.clone()),
+        }
+        i += 1;
+    }
+    out.into_iter().collect()
+}
+
+/// field_context returns a `.map_err` call that records the type and field an error occurred in.
+fn field_context(type_name: &str, field_name: &str) -> proc_macro2::TokenStream {
+    quote! {
+        .map_err(|e| ::bstream::Error::from(e).in_field(#type_name, #field_name))
     }
 }
 
@@ -122,31 +794,36 @@ fn get_func(
     little_endian: bool,
     varint: bool,
     read: bool,
+    value: proc_macro2::TokenStream,
 ) -> proc_macro2::TokenStream {
-    let field_id = &field.ident;
     let mut typ = field.ty.to_token_stream().to_string();
     if varint {
-        typ.insert(0, 'v');
+        typ = varint_typ(&typ);
     }
-    let b = gen_fn(&typ, little_endian, read, false);
+    let b = gen_fn(&typ, little_endian, read);
     if read {
-        return quote! {self.#field_id = #b(out)?;};
+        return quote! {#b(out)};
     }
-    quote! {#b(out, self.#field_id)?;}
+    quote! {#b(out, #value)?;}
 }
 
-fn gen_fn(
-    typ: &str,
-    little_endian: bool,
-    read: bool,
-    force_varint: bool,
-) -> proc_macro2::TokenStream {
-    let mut typ: String = typ.to_string();
-    let mut varint = typ == "vi32" || typ == "vu32" || typ == "vi64" || typ == "vu64";
-    if force_varint && !varint {
-        varint = true;
-        typ.insert(0, 'v');
+/// varint_typ returns the ReaderExt/WriterExt name of the varint encoding of an integer type. Signed varints
+/// are zigzag encoded on the wire, unsigned ones are plain.
+#[inline]
+fn varint_typ(typ: &str) -> String {
+    if typ.starts_with('i') {
+        format!("var_{}", typ)
+    } else {
+        format!("v{}", typ)
     }
+}
+
+fn gen_fn(typ: &str, little_endian: bool, read: bool) -> proc_macro2::TokenStream {
+    let typ: String = typ.to_string();
+    let mut varint = matches!(
+        typ.as_str(),
+        "vi32" | "vu32" | "vi64" | "vu64" | "var_i32" | "var_i64"
+    );
     let mut func = String::from(if read { "read_" } else { "write_" });
     let endian = Ident::new(
         if little_endian {
@@ -188,6 +865,20 @@ fn gen_fn(
     quote! {::byteorder::#ext::#func_id}
 }
 
+/// gen_len returns the expression for the encoded length of value, which is of the ReaderExt/WriterExt or
+/// byteorder type typ.
+fn gen_len(typ: &str, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match typ {
+        "vu32" | "vu64" | "vi32" | "vi64" => quote! {::bstream::varint_len((#value) as u64)},
+        "var_i32" | "var_i64" => quote! {::bstream::zigzag_len((#value) as i64)},
+        "bool" => quote! {1usize},
+        _ => {
+            let typ = Ident::new(typ, Span::call_site());
+            quote! {::std::mem::size_of::<#typ>()}
+        }
+    }
+}
+
 fn scan_attribute(field: &Field, little_endian: &mut bool, varint: &mut bool) {
     for attr in &(*field.attrs) {
         if let Meta::Path(path) = &attr.meta {
@@ -202,3 +893,151 @@ fn scan_attribute(field: &Field, little_endian: &mut bool, varint: &mut bool) {
         };
     }
 }
+
+/// len_prefix returns the LenPrefix variant named by a `#[U16Len]`, `#[U32Len]` or `#[I32Len]` attribute, which
+/// replaces the default varuint32 length prefix of String and Vec fields, along with the attribute.
+fn len_prefix(field: &Field) -> Option<(&'static str, &syn::Attribute)> {
+    field.attrs.iter().find_map(|attr| {
+        ["U16", "U32", "I32"]
+            .into_iter()
+            .find(|p| attr.path().is_ident(&format!("{}Len", p)))
+            .map(|p| (p, attr))
+    })
+}
+
+#[inline]
+fn is_vec(typ: &Type) -> bool {
+    match typ {
+        Type::Path(path) => path.path.segments.last().is_some_and(|s| s.ident == "Vec"),
+        _ => false,
+    }
+}
+
+/// vec_elem returns the element type of a Vec.
+fn vec_elem(typ: &Type) -> syn::Result<&Type> {
+    if let Type::Path(path) = typ {
+        if let Some(syn::PathArguments::AngleBracketed(args)) =
+            path.path.segments.last().map(|s| &s.arguments)
+        {
+            if let Some(syn::GenericArgument::Type(elem)) = args.args.first() {
+                return Ok(elem);
+            }
+        }
+    }
+    Err(syn::Error::new_spanned(typ, "expected a Vec"))
+}
+
+/// scan_max_len returns the limit of a `#[max_len(N)]` attribute, which lowers the max_len of the current
+/// context to N while the field is read, if it is higher.
+fn scan_max_len(field: &Field) -> syn::Result<Option<syn::Expr>> {
+    field
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("max_len"))
+        .map(|attr| {
+            attr.parse_args().map_err(|_| {
+                syn::Error::new_spanned(attr, "max_len expects a length, such as #[max_len(4096)]")
+            })
+        })
+        .transpose()
+}
+
+/// version_cond returns the condition under which a field marked with `#[since(...)]` or `#[until(...)]` is
+/// present in the version of the current Context. since is the first version with the field, and until the
+/// first version without it.
+fn version_cond(field: &Field) -> syn::Result<Option<proc_macro2::TokenStream>> {
+    let mut cond = None;
+    for attr in &field.attrs {
+        let op = if attr.path().is_ident("since") {
+            quote! {>=}
+        } else if attr.path().is_ident("until") {
+            quote! {<}
+        } else {
+            continue;
+        };
+        let version: syn::Expr = attr.parse_args().map_err(|_| {
+            syn::Error::new_spanned(
+                attr,
+                "since and until expect a version, such as #[since(662)]",
+            )
+        })?;
+        cond = and(
+            cond,
+            Some(quote! {::bstream::Context::current().version #op (#version)}),
+        );
+    }
+    Ok(cond)
+}
+
+#[inline]
+fn and(
+    a: Option<proc_macro2::TokenStream>,
+    b: Option<proc_macro2::TokenStream>,
+) -> Option<proc_macro2::TokenStream> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(quote! {(#a) && (#b)}),
+        (a, b) => a.or(b),
+    }
+}
+
+/// FieldAttrs holds the options given to a field through `#[bstream(...)]`.
+#[derive(Default)]
+struct FieldAttrs {
+    /// cond is the `if = "..."` condition. The field is only read and written when it holds, and is reset to
+    /// its default when it does not. It may refer to any field declared before this one through `self`.
+    cond: Option<syn::Expr>,
+    /// len_of is the `len_of = "..."` field whose length this field holds. The length is computed from that
+    /// field when writing, and that field is written without a length prefix of its own.
+    len_of: Option<Ident>,
+}
+
+fn field_attrs(field: &Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in &field.attrs {
+        if !attr.path().is_ident("bstream") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            let value: syn::LitStr = meta.value()?.parse()?;
+            if meta.path.is_ident("if") {
+                attrs.cond = Some(value.parse()?);
+            } else if meta.path.is_ident("len_of") {
+                attrs.len_of = Some(value.parse()?);
+            } else {
+                return Err(meta.error("unsupported bstream attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+/// TagAttrs holds the options given to a data-carrying enum and its variants through `#[bstream(...)]`.
+#[derive(Default)]
+struct TagAttrs {
+    /// tag is the `tag = ...` type of the discriminant written before the payload of a variant, such as u8 or
+    /// varu32.
+    tag: Option<Ident>,
+    /// tag_value is the `tag_value = ...` discriminant of a variant.
+    tag_value: Option<syn::Expr>,
+}
+
+fn tag_attrs(attrs: &[syn::Attribute]) -> syn::Result<TagAttrs> {
+    let mut tag_attrs = TagAttrs::default();
+    for attr in attrs {
+        if !attr.path().is_ident("bstream") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                tag_attrs.tag = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("tag_value") {
+                tag_attrs.tag_value = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unsupported bstream attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(tag_attrs)
+}
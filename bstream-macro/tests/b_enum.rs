@@ -0,0 +1,44 @@
+use bstream::Decode;
+use bstream_macro::b_enum;
+use common::roundtrip;
+
+mod common;
+
+#[derive(Debug, Clone, PartialEq)]
+#[b_enum(u8)]
+enum Leading {
+    #[unknown]
+    Unknown(u8),
+    First = 1,
+    Second = 2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[b_enum(i32, BigEndian)]
+enum Status {
+    Ok = 0,
+    Failed = 1,
+    #[unknown]
+    Unknown(i32),
+}
+
+#[test]
+fn unknown_before_known_variants() {
+    assert_eq!(roundtrip(&Leading::First), Leading::First);
+    assert_eq!(roundtrip(&Leading::Second), Leading::Second);
+    assert_eq!(roundtrip(&Leading::Unknown(7)), Leading::Unknown(7));
+    assert_eq!(Leading::decode(&mut &[2u8][..]).unwrap(), Leading::Second);
+}
+
+#[test]
+fn unknown_keeps_raw_value() {
+    assert_eq!(
+        Status::decode(&mut &[0, 0, 0, 1][..]).unwrap(),
+        Status::Failed
+    );
+    assert_eq!(
+        Status::decode(&mut &[0x7f, 0, 0, 9][..]).unwrap(),
+        Status::Unknown(0x7f000009)
+    );
+    assert_eq!(roundtrip(&Status::Unknown(-3)), Status::Unknown(-3));
+}
@@ -0,0 +1,13 @@
+use bstream::{Decode, Encode};
+
+/// roundtrip encodes v and decodes it back, checking that encoded_len is exact and that skip consumes as much
+/// as decode does.
+pub fn roundtrip<T: Decode + Encode>(v: &T) -> T {
+    let mut buf = vec![];
+    v.encode(&mut buf).unwrap();
+    assert_eq!(buf.len(), v.encoded_len());
+    let mut r = buf.as_slice();
+    T::skip(&mut r).unwrap();
+    assert!(r.is_empty());
+    T::decode(&mut buf.as_slice()).unwrap()
+}
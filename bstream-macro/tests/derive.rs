@@ -0,0 +1,259 @@
+use bstream::{to_vec, BigEndian, Context, Decode, Encode, VarU32};
+use bstream_macro::BStream;
+use common::roundtrip;
+
+mod common;
+
+/// Reserved fields are named after the locals of the generated code, which must not be shadowed by them.
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Reserved {
+    out: u8,
+    #[bstream(if = "self.out != 0")]
+    r#type: u16,
+    __encoded_len: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Tuple(u8, #[bstream(if = "self.0 == 1")] u8);
+
+#[derive(Debug, Clone, PartialEq, BStream)]
+#[bstream(tag = u8)]
+enum Tagged {
+    #[bstream(tag_value = 0)]
+    Out { out: u8 },
+    #[bstream(tag_value = 1)]
+    Pair(u8, u16),
+}
+
+/// Versioned has a field added in version 2 and one removed in version 3.
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Versioned {
+    a: u8,
+    #[since(2)]
+    added: u8,
+    #[until(3)]
+    removed: u8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Prefixed {
+    a: Vec<u8>,
+    #[U16Len]
+    b: Vec<u8>,
+    #[U32Len]
+    c: Vec<u16>,
+    #[I32Len]
+    d: Vec<u8>,
+    e: String,
+    #[U16Len]
+    f: String,
+    #[I32Len]
+    g: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Optional {
+    a: Option<u16>,
+    b: Option<String>,
+    c: [u16; 2],
+    d: [Option<u8>; 2],
+}
+
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Wrapped {
+    a: Vec<VarU32>,
+    b: BigEndian<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Signed {
+    #[Varint]
+    a: i32,
+    #[Varint]
+    b: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, BStream)]
+struct Limited {
+    #[max_len(4)]
+    name: String,
+}
+
+fn with_max_len<T>(max_len: usize, f: impl FnOnce() -> T) -> T {
+    Context {
+        max_len,
+        ..Context::current()
+    }
+    .scope(f)
+}
+
+fn with_version<T>(version: i32, f: impl FnOnce() -> T) -> T {
+    Context {
+        version,
+        ..Context::current()
+    }
+    .scope(f)
+}
+
+#[test]
+fn fields_named_like_generated_locals() {
+    let v = Reserved {
+        out: 1,
+        r#type: 2,
+        __encoded_len: 3,
+    };
+    assert_eq!(roundtrip(&v), v);
+    let v = Reserved {
+        out: 0,
+        r#type: 0,
+        __encoded_len: 3,
+    };
+    assert_eq!(roundtrip(&v), v);
+    assert_eq!(roundtrip(&Tuple(1, 2)), Tuple(1, 2));
+    assert_eq!(roundtrip(&Tuple(0, 0)), Tuple(0, 0));
+    assert_eq!(roundtrip(&Tagged::Out { out: 4 }), Tagged::Out { out: 4 });
+    assert_eq!(roundtrip(&Tagged::Pair(1, 2)), Tagged::Pair(1, 2));
+}
+
+#[test]
+fn versioned_fields() {
+    let v = Versioned {
+        a: 1,
+        added: 2,
+        removed: 3,
+    };
+    for (version, encoded, decoded) in [
+        (
+            1,
+            vec![1, 3],
+            Versioned {
+                added: 0,
+                ..v.clone()
+            },
+        ),
+        (2, vec![1, 2, 3], v.clone()),
+        (
+            3,
+            vec![1, 2],
+            Versioned {
+                removed: 0,
+                ..v.clone()
+            },
+        ),
+    ] {
+        with_version(version, || {
+            let mut buf = vec![];
+            v.encode(&mut buf).unwrap();
+            assert_eq!(buf, encoded, "version {}", version);
+            assert_eq!(roundtrip(&v), decoded, "version {}", version);
+        });
+    }
+    // without a version, the latest layout is used.
+    assert_eq!(roundtrip(&v), with_version(3, || roundtrip(&v)));
+}
+
+#[test]
+fn max_len_only_tightens() {
+    let encode = |name: &str| {
+        let mut buf = vec![];
+        Limited { name: name.into() }.encode(&mut buf).unwrap();
+        buf
+    };
+    assert!(Limited::decode(&mut encode("abcd").as_slice()).is_ok());
+    assert!(Limited::decode(&mut encode("abcde").as_slice()).is_err());
+    assert!(Limited::skip(&mut encode("abcde").as_slice()).is_err());
+    // a stricter limit of the context still applies to the field.
+    with_max_len(2, || {
+        assert!(Limited::decode(&mut encode("ab").as_slice()).is_ok());
+        assert!(Limited::decode(&mut encode("abc").as_slice()).is_err());
+        assert!(Limited::skip(&mut encode("abc").as_slice()).is_err());
+    });
+}
+
+#[test]
+fn signed_varints_are_zigzag_encoded() {
+    let v = Signed { a: -1, b: 1 };
+    let mut buf = vec![];
+    v.encode(&mut buf).unwrap();
+    assert_eq!(buf, [0x01, 0x02]);
+    assert_eq!(roundtrip(&v), v);
+    let v = Signed {
+        a: i32::MIN,
+        b: i64::MIN,
+    };
+    let mut buf = vec![];
+    v.encode(&mut buf).unwrap();
+    assert_eq!(buf[..5], [0xff, 0xff, 0xff, 0xff, 0x0f]);
+    assert_eq!(buf.len(), 15);
+    assert_eq!(roundtrip(&v), v);
+}
+
+#[test]
+fn length_prefixes() {
+    let v = Prefixed {
+        a: vec![1],
+        b: vec![2, 3],
+        c: vec![0x0504],
+        d: vec![6],
+        e: "e".into(),
+        f: "ff".into(),
+        g: "g".into(),
+    };
+    #[rustfmt::skip]
+    assert_eq!(
+        to_vec(&v).unwrap(),
+        [
+            1, 1,
+            2, 0, 2, 3,
+            1, 0, 0, 0, 4, 5,
+            1, 0, 0, 0, 6,
+            1, b'e',
+            2, 0, b'f', b'f',
+            1, 0, 0, 0, b'g',
+        ]
+    );
+    assert_eq!(roundtrip(&v), v);
+    // a length of 128 takes two bytes as a varuint32, and still two as a u16.
+    let v = Prefixed {
+        a: vec![0; 128],
+        b: vec![0; 128],
+        ..Default::default()
+    };
+    let bytes = to_vec(&v).unwrap();
+    assert_eq!(bytes[..2], [0x80, 0x01]);
+    assert_eq!(bytes[130..132], [0x80, 0x00]);
+    assert_eq!(roundtrip(&v), v);
+    // a negative i32 length is rejected rather than read as a huge one.
+    let mut bytes = to_vec(&Prefixed::default()).unwrap();
+    bytes[7..11].copy_from_slice(&(-1i32).to_le_bytes());
+    assert!(Prefixed::decode(&mut bytes.as_slice()).is_err());
+}
+
+#[test]
+fn options_and_arrays() {
+    let v = Optional {
+        a: Some(0x0201),
+        b: None,
+        c: [3, 4],
+        d: [None, Some(5)],
+    };
+    assert_eq!(to_vec(&v).unwrap(), [1, 1, 2, 0, 3, 0, 4, 0, 0, 1, 5]);
+    assert_eq!(roundtrip(&v), v);
+    let v = Optional {
+        a: None,
+        b: Some("b".into()),
+        ..Default::default()
+    };
+    assert_eq!(to_vec(&v).unwrap(), [0, 1, 1, b'b', 0, 0, 0, 0, 0, 0]);
+    assert_eq!(roundtrip(&v), v);
+}
+
+#[test]
+fn wrappers() {
+    let v = Wrapped {
+        a: vec![VarU32(1), VarU32(300)],
+        b: BigEndian(0x01020304),
+    };
+    assert_eq!(to_vec(&v).unwrap(), [2, 1, 0xac, 0x02, 1, 2, 3, 4]);
+    assert_eq!(roundtrip(&v), v);
+}
@@ -0,0 +1,43 @@
+use bstream::{Decode, Encode};
+use bstream_macro::BStream;
+use serde::{Deserialize, Serialize};
+
+/// Mixed is encoded the same by the derive and by serde_wire, which marks its varint and big endian fields with
+/// the serde_wire modules in place of the encoding attributes.
+#[derive(Debug, Clone, PartialEq, BStream, Serialize, Deserialize)]
+struct Mixed {
+    a: u8,
+    #[Varint]
+    #[serde(with = "bstream::serde_wire::varint")]
+    b: u32,
+    #[Varint]
+    #[serde(with = "bstream::serde_wire::varint")]
+    c: i64,
+    #[BigEndian]
+    #[serde(with = "bstream::serde_wire::big_endian")]
+    d: i32,
+    e: String,
+    f: Vec<u16>,
+    g: Option<bool>,
+    h: Option<String>,
+}
+
+#[test]
+fn serde_wire_matches_derive() {
+    let v = Mixed {
+        a: 1,
+        b: 300,
+        c: -3,
+        d: 7,
+        e: "hey".into(),
+        f: vec![1, 2],
+        g: Some(true),
+        h: None,
+    };
+    let mut buf = vec![];
+    v.encode(&mut buf).unwrap();
+    let bytes = bstream::serde_wire::to_vec(&v).unwrap();
+    assert_eq!(bytes, buf);
+    assert_eq!(bstream::serde_wire::from_slice::<Mixed>(&buf).unwrap(), v);
+    assert_eq!(Mixed::decode(&mut bytes.as_slice()).unwrap(), v);
+}
@@ -0,0 +1,64 @@
+use std::cell::Cell;
+
+/// DEFAULT_MAX_LEN is the max_len of the default [Context], 8 MiB.
+pub const DEFAULT_MAX_LEN: usize = 8 << 20;
+
+/// DEFAULT_MAX_DEPTH is the max_depth of the default [Context].
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+thread_local! {
+    static CURRENT: Cell<Context> = Cell::new(Context::default());
+}
+
+/// Context holds the decoding settings honoured by every read performed on the current thread. It is
+/// installed with [Context::scope], so a connection can decode its peer's packets with stricter settings
+/// without threading them through every `read` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    /// strict rejects input that decodes to a valid value but could not have been produced by a canonical
+    /// encoder, such as varints padded with redundant continuation bytes.
+    pub strict: bool,
+    /// max_len is the largest length prefix accepted for any length-prefixed value, such as a byte slice. It
+    /// bounds both the time and the memory spent on a single value.
+    pub max_len: usize,
+    /// max_depth is the deepest nesting accepted for recursive values, such as NBT compounds and lists. It
+    /// guards the stack against input that nests values without end.
+    pub max_depth: usize,
+    /// version is the version of the format being read or written, which decides whether fields marked with
+    /// `#[since(...)]` or `#[until(...)]` are present. It defaults to i32::MAX, the latest version.
+    pub version: i32,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            max_len: DEFAULT_MAX_LEN,
+            max_depth: DEFAULT_MAX_DEPTH,
+            version: i32::MAX,
+        }
+    }
+}
+
+impl Context {
+    /// current returns the context installed on the current thread.
+    #[inline]
+    pub fn current() -> Self {
+        CURRENT.with(Cell::get)
+    }
+
+    /// scope runs f with self as the current context, restoring the previous context afterwards, even if f
+    /// panics.
+    pub fn scope<T>(self, f: impl FnOnce() -> T) -> T {
+        struct Restore(Context);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT.with(|c| c.set(self.0))
+            }
+        }
+
+        let _restore = Restore(CURRENT.with(|c| c.replace(self)));
+        f()
+    }
+}
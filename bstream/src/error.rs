@@ -0,0 +1,194 @@
+use std::{error, fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// ErrorKind is the reason a value could not be decoded or encoded.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// UnexpectedEof means the input ended in the middle of a value.
+    UnexpectedEof,
+    /// InvalidDiscriminant means an enum was read with a value that none of its variants has.
+    InvalidDiscriminant(i128),
+    /// VarintOverflow means a varint was terminated in time, but its value does not fit the width being read.
+    VarintOverflow,
+    /// VarintUnterminated means a varint was still continuing after the maximum number of bytes for its width.
+    VarintUnterminated,
+    /// NonCanonicalVarint means a varint ends in redundant zero bytes. It is only returned in strict mode.
+    NonCanonicalVarint,
+    /// LengthLimitExceeded means a length prefix is larger than the limit allowed for it.
+    LengthLimitExceeded { len: u64, limit: u64 },
+    /// NegativeLength means a signed length prefix was negative.
+    NegativeLength(i64),
+    /// InvalidUtf8 means a string was not valid UTF-8.
+    InvalidUtf8,
+    /// DepthLimitExceeded means a recursive value was nested deeper than the limit allowed for it.
+    DepthLimitExceeded(usize),
+    /// UnexpectedTag means an NBT tag had a different type than the one expected, such as an element of a list
+    /// that differs from the first one.
+    UnexpectedTag { expected: u8, found: u8 },
+    /// MissingTag means a compound had no tag with the name of a field.
+    MissingTag,
+    /// Message is an error described only by its message, such as one reported by a serde implementation, or
+    /// by the serde format for a value it cannot represent.
+    Message(String),
+    /// Io is any other error returned by the underlying reader or writer.
+    Io(io::Error),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedEof => f.write_str("unexpected end of input"),
+            ErrorKind::InvalidDiscriminant(v) => write!(f, "invalid discriminant {}", v),
+            ErrorKind::VarintOverflow => f.write_str("varint overflows its width"),
+            ErrorKind::VarintUnterminated => f.write_str("varint has no ending"),
+            ErrorKind::NonCanonicalVarint => f.write_str("varint is not canonically encoded"),
+            ErrorKind::LengthLimitExceeded { len, limit } => {
+                write!(f, "length {} exceeds limit {}", len, limit)
+            }
+            ErrorKind::NegativeLength(len) => write!(f, "negative length {}", len),
+            ErrorKind::InvalidUtf8 => f.write_str("invalid utf-8"),
+            ErrorKind::DepthLimitExceeded(limit) => {
+                write!(f, "nesting exceeds depth limit {}", limit)
+            }
+            ErrorKind::UnexpectedTag { expected, found } => {
+                write!(f, "expected tag type {}, found {}", expected, found)
+            }
+            ErrorKind::MissingTag => f.write_str("missing tag"),
+            ErrorKind::Message(msg) => f.write_str(msg),
+            ErrorKind::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Error is returned by every read and write in bstream. Besides its kind it records, where known, the type
+/// and field that failed and the byte offset at which it failed.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    type_name: Option<&'static str>,
+    field: Option<&'static str>,
+    offset: Option<u64>,
+}
+
+impl Error {
+    #[inline]
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            type_name: None,
+            field: None,
+            offset: None,
+        }
+    }
+
+    #[inline]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// type_name is the name of the innermost type that failed to decode, if known.
+    #[inline]
+    pub fn type_name(&self) -> Option<&'static str> {
+        self.type_name
+    }
+
+    /// field is the name of the innermost field that failed to decode, if known.
+    #[inline]
+    pub fn field(&self) -> Option<&'static str> {
+        self.field
+    }
+
+    /// offset is the number of bytes consumed from the input when the error occurred, if known.
+    #[inline]
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// in_type records the type that failed, unless a type was already recorded further down.
+    pub fn in_type(mut self, type_name: &'static str) -> Self {
+        if self.type_name.is_none() {
+            self.type_name = Some(type_name);
+        }
+        self
+    }
+
+    /// in_field records the field that failed, unless a field was already recorded further down.
+    pub fn in_field(mut self, type_name: &'static str, field: &'static str) -> Self {
+        if self.field.is_none() {
+            self.type_name = Some(type_name);
+            self.field = Some(field);
+        }
+        self
+    }
+
+    /// at_offset records the offset at which the error occurred, unless one was already recorded.
+    pub fn at_offset(mut self, offset: u64) -> Self {
+        if self.offset.is_none() {
+            self.offset = Some(offset);
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut located = true;
+        match (self.type_name, self.field) {
+            (Some(t), Some(field)) => write!(f, "{}.{}", t, field)?,
+            (Some(t), None) => f.write_str(t)?,
+            _ => located = false,
+        }
+        if let Some(offset) = self.offset {
+            if located {
+                f.write_str(" ")?;
+            }
+            write!(f, "at offset {}", offset)?;
+            located = true;
+        }
+        if located {
+            f.write_str(": ")?;
+        }
+        self.kind.fmt(f)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    #[inline]
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            return Self::new(ErrorKind::UnexpectedEof);
+        }
+        Self::new(ErrorKind::Io(e))
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e.kind {
+            ErrorKind::Io(e) => e,
+            kind => {
+                let io_kind = match kind {
+                    ErrorKind::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+                    _ => io::ErrorKind::InvalidData,
+                };
+                io::Error::new(io_kind, Error { kind, ..e })
+            }
+        }
+    }
+}
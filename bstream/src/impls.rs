@@ -0,0 +1,258 @@
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{Decode, Encode, LenPrefix, ReaderExt, Result, WriterExt};
+
+macro_rules! impl_primitive {
+    ($($typ:ty, $read:ident, $write:ident;)*) => {
+        $(
+            impl Decode for $typ {
+                #[inline]
+                fn decode(out: &mut impl Read) -> Result<Self> {
+                    Ok(out.$read::<LittleEndian>()?)
+                }
+            }
+
+            impl Encode for $typ {
+                #[inline]
+                fn encode(&self, out: &mut impl Write) -> Result<()> {
+                    Ok(out.$write::<LittleEndian>(*self)?)
+                }
+
+                #[inline]
+                fn encoded_len(&self) -> usize {
+                    ::std::mem::size_of::<$typ>()
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive! {
+    u16, read_u16, write_u16;
+    u32, read_u32, write_u32;
+    u64, read_u64, write_u64;
+    i16, read_i16, write_i16;
+    i32, read_i32, write_i32;
+    i64, read_i64, write_i64;
+    f32, read_f32, write_f32;
+    f64, read_f64, write_f64;
+}
+
+/// u8 reads, skips and writes sequences of bytes at once, so that `Vec<u8>` and other byte sequences are not
+/// handled one byte at a time.
+impl Decode for u8 {
+    #[inline]
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        Ok(out.read_u8()?)
+    }
+
+    #[inline]
+    fn decode_seq(out: &mut impl Read, len: usize) -> Result<Vec<Self>> {
+        crate::read_bytes(out, len as u64)
+    }
+
+    #[inline]
+    fn skip_seq(out: &mut impl Read, len: usize) -> Result<()> {
+        crate::skip_bytes(out, len as u64)
+    }
+}
+
+impl Encode for u8 {
+    #[inline]
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        Ok(out.write_u8(*self)?)
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn encode_seq(v: &[Self], out: &mut impl Write) -> Result<()> {
+        Ok(out.write_all(v)?)
+    }
+}
+
+impl Decode for i8 {
+    #[inline]
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        Ok(out.read_i8()?)
+    }
+}
+
+impl Encode for i8 {
+    #[inline]
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        Ok(out.write_i8(*self)?)
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        1
+    }
+}
+
+impl Decode for bool {
+    #[inline]
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        out.read_bool()
+    }
+}
+
+impl Encode for bool {
+    #[inline]
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        Ok(out.write_bool(*self)?)
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        1
+    }
+}
+
+impl Decode for String {
+    #[inline]
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        out.read_string()
+    }
+
+    #[inline]
+    fn skip(out: &mut impl Read) -> Result<()> {
+        let len = LenPrefix::Vu32.read(out)?;
+        crate::skip_bytes(out, len)
+    }
+}
+
+impl Encode for String {
+    #[inline]
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        out.write_string(self)
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        LenPrefix::Vu32.encoded_len(self.len()) + self.len()
+    }
+}
+
+/// Vec is prefixed with its length as a varuint32. Other prefixes are available through [crate::read_seq]
+/// and [crate::write_seq].
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        let len = LenPrefix::Vu32.read(out)?;
+        crate::read_seq(out, len)
+    }
+
+    fn skip(out: &mut impl Read) -> Result<()> {
+        let len = LenPrefix::Vu32.read(out)?;
+        crate::skip_seq::<T>(out, len)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        LenPrefix::Vu32.write(out, self.len())?;
+        crate::write_seq(out, self)
+    }
+
+    fn encoded_len(&self) -> usize {
+        LenPrefix::Vu32.encoded_len(self.len()) + crate::seq_len(self)
+    }
+}
+
+/// Option is preceded by a bool that is true if the value is present.
+impl<T: Decode> Decode for Option<T> {
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        if out.read_bool()? {
+            return Ok(Some(T::decode(out)?));
+        }
+        Ok(None)
+    }
+
+    fn skip(out: &mut impl Read) -> Result<()> {
+        if out.read_bool()? {
+            T::skip(out)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        out.write_bool(self.is_some())?;
+        if let Some(v) = self {
+            v.encode(out)?;
+        }
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        1 + self.as_ref().map_or(0, T::encoded_len)
+    }
+}
+
+/// Arrays have a fixed length, so they are written without a length prefix.
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        match T::decode_seq(out, N)?.try_into() {
+            Ok(v) => Ok(v),
+            Err(_) => unreachable!("exactly N values are decoded"),
+        }
+    }
+
+    fn skip(out: &mut impl Read) -> Result<()> {
+        T::skip_seq(out, N)
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        crate::write_seq(out, self)
+    }
+
+    fn encoded_len(&self) -> usize {
+        crate::seq_len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    /// Reads counts the calls to read made through it.
+    struct Reads<R>(R, usize);
+
+    impl<R: Read> Read for Reads<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1 += 1;
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn byte_sequences_are_read_at_once() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let mut buf = vec![];
+        bytes.encode(&mut buf).unwrap();
+        assert_eq!(buf[..2], [0x80, 0x02]);
+        assert_eq!(buf[2..], bytes[..]);
+
+        let mut r = Reads(buf.as_slice(), 0);
+        assert_eq!(Vec::<u8>::decode(&mut r).unwrap(), bytes);
+        assert_eq!(r.1, 3);
+        let mut r = Reads(buf.as_slice(), 0);
+        Vec::<u8>::skip(&mut r).unwrap();
+        assert!(r.0.is_empty());
+        assert!(r.1 < 8);
+
+        let mut r = Reads(&bytes[..], 0);
+        assert_eq!(<[u8; 256]>::decode(&mut r).unwrap()[..], bytes[..]);
+        assert_eq!(r.1, 1);
+    }
+}
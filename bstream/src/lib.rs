@@ -1,53 +1,115 @@
 use io::Write;
 use std::io;
-use std::io::{Error, ErrorKind, Read};
+use std::io::Read;
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
+pub use context::{Context, DEFAULT_MAX_DEPTH, DEFAULT_MAX_LEN};
+pub use error::{Error, ErrorKind, Result};
+pub use slice::{SliceReader, Vu32LenBytes};
+pub use wrappers::{BigEndian, LittleEndian, VarI32, VarI64, VarU32, VarU64};
+
+mod context;
+mod error;
+mod impls;
+pub mod nbt;
+#[cfg(feature = "serde")]
+pub mod serde_wire;
+mod slice;
+mod wrappers;
+
 impl<T: Read + Sized> ReaderExt for T {}
 
 pub trait ReaderExt: Read + Sized {
     #[inline]
-    fn read_bool(&mut self) -> io::Result<bool> {
-        self.read_u8().map(|u| u == 1)
+    fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? == 1)
+    }
+
+    #[inline]
+    fn read_vu32(&mut self) -> Result<u32> {
+        read_variable(self, 32).map(|v| v as u32)
+    }
+
+    #[inline]
+    fn read_vu64(&mut self) -> Result<u64> {
+        read_variable(self, 64)
+    }
+
+    /// read_vi32 reads a varint that holds the two's complement of an i32 sign-extended to 64 bits, which is
+    /// what write_vi32 produces.
+    #[inline]
+    fn read_vi32(&mut self) -> Result<i32> {
+        read_variable(self, 64)
+            .and_then(|v| i32::try_from(v as i64).map_err(|_| ErrorKind::VarintOverflow.into()))
     }
 
     #[inline]
-    fn read_vu32(&mut self) -> io::Result<u32> {
-        read_variable(self).map(|v: u64| v as u32)
+    fn read_vi64(&mut self) -> Result<i64> {
+        read_variable(self, 64).map(|v| v as i64)
     }
 
+    /// read_var_i32 reads a zigzag encoded signed varint, as used by Bedrock for signed varints.
     #[inline]
-    fn read_vu64(&mut self) -> io::Result<u64> {
-        read_variable(self)
+    fn read_var_i32(&mut self) -> Result<i32> {
+        self.read_vu32()
+            .map(|v| (v >> 1) as i32 ^ -((v & 1) as i32))
     }
 
+    /// read_var_i64 reads a zigzag encoded signed varlong, as used by Bedrock for signed varlongs.
     #[inline]
-    fn read_vi32(&mut self) -> io::Result<i32> {
-        read_variable(self).map(|v: u64| v as i32)
+    fn read_var_i64(&mut self) -> Result<i64> {
+        self.read_vu64()
+            .map(|v| (v >> 1) as i64 ^ -((v & 1) as i64))
     }
 
+    /// read_string reads a string prefixed with its length as a varuint32, the default string encoding of
+    /// Bedrock.
     #[inline]
-    fn read_vi64(&mut self) -> io::Result<i64> {
-        read_variable(self).map(|v: u64| v as i64)
+    fn read_string(&mut self) -> Result<String> {
+        let len = LenPrefix::Vu32.read(self)?;
+        read_str(self, len)
+    }
+
+    /// read_string_u16 reads a string prefixed with its length as a little endian u16, as used by NBT.
+    #[inline]
+    fn read_string_u16(&mut self) -> Result<String> {
+        let len = LenPrefix::U16.read(self)?;
+        read_str(self, len)
+    }
+
+    /// read_string_i32 reads a string prefixed with its length as a little endian i32, as used by the login
+    /// chain.
+    #[inline]
+    fn read_string_i32(&mut self) -> Result<String> {
+        let len = LenPrefix::I32.read(self)?;
+        read_str(self, len)
     }
 }
 
+/// read_variable reads a varint of at most `bits` bits, which takes up to 5 bytes for 32 bits and 10 bytes
+/// for 64 bits.
 #[inline]
-fn read_variable<T: Into<u64> + From<u64>>(r: &mut impl Read) -> io::Result<T> {
+fn read_variable(r: &mut impl Read, bits: u32) -> Result<u64> {
     let mut v = 0u64;
-    let mut shift = 0u64;
-    while shift < 64 {
-        let vx = r.read_u8()? as u64;
-        let val = vx & 0b01111111u64;
+    let mut shift = 0u32;
+    while shift < bits {
+        let vx = r.read_u8()?;
+        let val = (vx & 0b01111111u8) as u64;
+        if shift + 7 > bits && val >> (bits - shift) != 0 {
+            return Err(ErrorKind::VarintOverflow.into());
+        }
         v |= val << shift;
-        let last = (vx & 0b10000000u64) == 0;
+        let last = (vx & 0b10000000u8) == 0;
         if last {
-            return Ok(v.into());
+            if vx == 0 && shift > 0 && Context::current().strict {
+                return Err(ErrorKind::NonCanonicalVarint.into());
+            }
+            return Ok(v);
         }
         shift += 7;
     }
-    Err(Error::new(ErrorKind::InvalidData, "varint has no ending."))
+    Err(ErrorKind::VarintUnterminated.into())
 }
 
 #[inline]
@@ -88,23 +150,165 @@ pub trait WriterExt: Write + Sized {
     fn write_vi64(&mut self, v: i64) -> io::Result<()> {
         write_variable(self, v as u64)
     }
+
+    /// write_var_i32 writes a zigzag encoded signed varint, so that small negative values stay short.
+    #[inline]
+    fn write_var_i32(&mut self, v: i32) -> io::Result<()> {
+        self.write_vu32(((v << 1) ^ (v >> 31)) as u32)
+    }
+
+    /// write_var_i64 writes a zigzag encoded signed varlong, so that small negative values stay short.
+    #[inline]
+    fn write_var_i64(&mut self, v: i64) -> io::Result<()> {
+        self.write_vu64(((v << 1) ^ (v >> 63)) as u64)
+    }
+
+    /// write_string writes a string prefixed with its length as a varuint32.
+    #[inline]
+    fn write_string(&mut self, v: &str) -> Result<()> {
+        LenPrefix::Vu32.write(self, v.len())?;
+        Ok(self.write_all(v.as_bytes())?)
+    }
+
+    /// write_string_u16 writes a string prefixed with its length as a little endian u16.
+    #[inline]
+    fn write_string_u16(&mut self, v: &str) -> Result<()> {
+        LenPrefix::U16.write(self, v.len())?;
+        Ok(self.write_all(v.as_bytes())?)
+    }
+
+    /// write_string_i32 writes a string prefixed with its length as a little endian i32.
+    #[inline]
+    fn write_string_i32(&mut self, v: &str) -> Result<()> {
+        LenPrefix::I32.write(self, v.len())?;
+        Ok(self.write_all(v.as_bytes())?)
+    }
 }
 
+/// Decode is implemented by types that can be decoded from a reader. Values are constructed from what is read,
+/// so unlike BinaryStream, decoding does not need an existing value to decode into.
+pub trait Decode: Sized {
+    fn decode(out: &mut impl Read) -> Result<Self>;
+
+    /// skip advances out past a value without keeping it. The default implementation decodes the value and
+    /// drops it, types that can be skipped without decoding them should override it.
+    fn skip(out: &mut impl Read) -> Result<()> {
+        Self::decode(out).map(drop)
+    }
+
+    /// decode_seq decodes len values one after another, as [read_seq] does once it has checked len. It is
+    /// overridden by u8, so that byte sequences are read at once rather than byte by byte.
+    #[doc(hidden)]
+    fn decode_seq(out: &mut impl Read, len: usize) -> Result<Vec<Self>> {
+        // len is bounded by max_len, but the input may still hold far fewer values than it claims, so the
+        // vector is left to grow as values actually arrive.
+        let mut v = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            v.push(Self::decode(out)?);
+        }
+        Ok(v)
+    }
+
+    /// skip_seq skips len values one after another, as [skip_seq] does once it has checked len. It is
+    /// overridden by u8, so that byte sequences are skipped at once rather than byte by byte.
+    #[doc(hidden)]
+    fn skip_seq(out: &mut impl Read, len: usize) -> Result<()> {
+        for _ in 0..len {
+            Self::skip(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encode is implemented by types that can be encoded to a writer.
+pub trait Encode {
+    fn encode(&self, out: &mut impl Write) -> Result<()>;
+
+    /// encoded_len returns the number of bytes encode produces, so that buffers can be sized and compression
+    /// decided on before encoding. The default implementation encodes the value and counts the bytes, types
+    /// that know their size should override it.
+    fn encoded_len(&self) -> usize {
+        let mut w = LenCounter(0);
+        // a value that fails to encode is counted up to the failure, encode reports the error itself.
+        let _ = self.encode(&mut w);
+        w.0
+    }
+
+    /// encode_seq encodes the values in v one after another, as [write_seq] does. It is overridden by u8, so
+    /// that byte sequences are written at once rather than byte by byte.
+    #[doc(hidden)]
+    fn encode_seq(v: &[Self], out: &mut impl Write) -> Result<()>
+    where
+        Self: Sized,
+    {
+        for elem in v {
+            elem.encode(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// BinaryStream reads a value in place. Types implementing it implement Encode through it, and Decode if they
+/// implement Default as well. New types should implement Decode and Encode directly.
 pub trait BinaryStream {
-    fn read(&mut self, out: &mut impl Read) -> io::Result<()>;
-    fn write(&self, out: &mut impl Write) -> io::Result<()>;
+    fn read(&mut self, out: &mut impl Read) -> Result<()>;
+    fn write(&self, out: &mut impl Write) -> Result<()>;
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct Vu32LenByteSlice(pub Vec<u8>);
+impl<T: BinaryStream + Default> Decode for T {
+    #[inline]
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        let mut v = T::default();
+        v.read(out)?;
+        Ok(v)
+    }
+}
+
+impl<T: BinaryStream> Encode for T {
+    #[inline]
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        self.write(out)
+    }
+}
+
+/// LenCounter is a writer that discards the bytes written to it and only counts them.
+struct LenCounter(usize);
+
+impl Write for LenCounter {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
 
-pub trait EnumBinaryStream {
-    fn read(out: &mut impl Read) -> io::Result<Self>
-        where
-            Self: Sized;
-    fn write(&self, out: &mut impl Write) -> io::Result<()>;
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// varint_len returns the number of bytes v takes up as a varint.
+#[inline]
+pub fn varint_len(v: u64) -> usize {
+    (64 - (v | 1).leading_zeros() as usize).div_ceil(7)
 }
 
+/// zigzag_len returns the number of bytes v takes up as a zigzag encoded varint, for both var_i32 and var_i64.
+#[inline]
+pub fn zigzag_len(v: i64) -> usize {
+    varint_len(((v << 1) ^ (v >> 63)) as u64)
+}
+
+/// to_vec encodes v into a vector that is allocated with the exact size up front.
+pub fn to_vec<T: Encode + ?Sized>(v: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(v.encoded_len());
+    v.encode(&mut buf)?;
+    Ok(buf)
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Vu32LenByteSlice(pub Vec<u8>);
+
 impl Vu32LenByteSlice {
     #[inline]
     pub fn from<T: Into<Vec<u8>>>(v: T) -> Self {
@@ -112,22 +316,317 @@ impl Vu32LenByteSlice {
     }
 
     #[inline]
-    pub fn read(out: &mut impl Read) -> io::Result<Self> {
-        let mut v = Self::default();
-        v.0.clear();
-        let len = out.read_vu32()?;
-        for _ in 0..len {
-            v.0.push(out.read_u8()?)
-        }
-        Ok(v)
+    pub fn read(out: &mut impl Read) -> Result<Self> {
+        let len = LenPrefix::Vu32.read(out)?;
+        Ok(Self(read_bytes(out, len)?))
+    }
+
+    #[inline]
+    pub fn write(&self, out: &mut impl Write) -> Result<()> {
+        LenPrefix::Vu32.write(out, self.0.len())?;
+        out.write_all(&self.0)?;
+        Ok(())
+    }
+}
+
+impl Decode for Vu32LenByteSlice {
+    #[inline]
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        Vu32LenByteSlice::read(out)
+    }
+
+    #[inline]
+    fn skip(out: &mut impl Read) -> Result<()> {
+        let len = LenPrefix::Vu32.read(out)?;
+        skip_bytes(out, len)
+    }
+}
+
+impl Encode for Vu32LenByteSlice {
+    #[inline]
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        Vu32LenByteSlice::write(self, out)
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        LenPrefix::Vu32.encoded_len(self.0.len()) + self.0.len()
+    }
+}
+
+/// LenPrefix is the encoding of the length that precedes a string or a sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LenPrefix {
+    Vu32,
+    U16,
+    U32,
+    I32,
+}
+
+impl LenPrefix {
+    /// read reads a length. The length is not checked against the current [Context] yet, that is left to the
+    /// function reading the value it prefixes, such as [read_bytes] or [read_seq].
+    pub fn read(self, r: &mut impl Read) -> Result<u64> {
+        Ok(match self {
+            LenPrefix::Vu32 => r.read_vu32()? as u64,
+            LenPrefix::U16 => r.read_u16::<byteorder::LittleEndian>()? as u64,
+            LenPrefix::U32 => r.read_u32::<byteorder::LittleEndian>()? as u64,
+            LenPrefix::I32 => {
+                let len = r.read_i32::<byteorder::LittleEndian>()?;
+                if len < 0 {
+                    return Err(ErrorKind::NegativeLength(len as i64).into());
+                }
+                len as u64
+            }
+        })
     }
 
+    /// encoded_len returns the number of bytes the prefix of len takes up.
     #[inline]
-    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
-        out.write_vu32(self.0.len() as u32)?;
-        for elem in self.0.iter() {
-            out.write_u8(*elem)?
+    pub fn encoded_len(self, len: usize) -> usize {
+        match self {
+            LenPrefix::Vu32 => varint_len(len as u64),
+            LenPrefix::U16 => 2,
+            LenPrefix::U32 | LenPrefix::I32 => 4,
+        }
+    }
+
+    /// write writes len, failing if it is too large for the prefix.
+    pub fn write(self, w: &mut impl Write, len: usize) -> Result<()> {
+        let limit = match self {
+            LenPrefix::Vu32 | LenPrefix::U32 => u32::MAX as u64,
+            LenPrefix::U16 => u16::MAX as u64,
+            LenPrefix::I32 => i32::MAX as u64,
+        };
+        if len as u64 > limit {
+            return Err(ErrorKind::LengthLimitExceeded {
+                len: len as u64,
+                limit,
+            }
+            .into());
+        }
+        match self {
+            LenPrefix::Vu32 => w.write_vu32(len as u32)?,
+            LenPrefix::U16 => w.write_u16::<byteorder::LittleEndian>(len as u16)?,
+            LenPrefix::U32 => w.write_u32::<byteorder::LittleEndian>(len as u32)?,
+            LenPrefix::I32 => w.write_i32::<byteorder::LittleEndian>(len as i32)?,
         }
         Ok(())
     }
 }
+
+/// check_len returns len as a usize if it does not exceed the max_len of the current [Context]. Every
+/// length-prefixed value must pass its length through check_len before acting on it.
+#[inline]
+pub fn check_len(len: u64) -> Result<usize> {
+    let limit = Context::current().max_len;
+    if len > limit as u64 {
+        return Err(ErrorKind::LengthLimitExceeded {
+            len,
+            limit: limit as u64,
+        }
+        .into());
+    }
+    Ok(len as usize)
+}
+
+/// read_bytes reads exactly len bytes, after checking len with [check_len].
+#[inline]
+pub fn read_bytes(r: &mut impl Read, len: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![0; check_len(len)?];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// skip_bytes advances r past len bytes, after checking len with [check_len].
+pub fn skip_bytes(r: &mut impl Read, len: u64) -> Result<()> {
+    let len = check_len(len)? as u64;
+    if io::copy(&mut r.take(len), &mut io::sink())? < len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// read_str reads a UTF-8 string of len bytes, after checking len with [check_len].
+#[inline]
+pub fn read_str(r: &mut impl Read, len: u64) -> Result<String> {
+    String::from_utf8(read_bytes(r, len)?).map_err(|_| ErrorKind::InvalidUtf8.into())
+}
+
+/// read_seq reads len values of T one after another, after checking len with [check_len]. A sequence of u8 is
+/// read at once, like [read_bytes] does.
+pub fn read_seq<T: Decode>(r: &mut impl Read, len: u64) -> Result<Vec<T>> {
+    T::decode_seq(r, check_len(len)?)
+}
+
+/// skip_seq advances r past len values of T, after checking len with [check_len].
+pub fn skip_seq<T: Decode>(r: &mut impl Read, len: u64) -> Result<()> {
+    T::skip_seq(r, check_len(len)?)
+}
+
+/// write_seq writes the values in v one after another, without a length prefix.
+pub fn write_seq<T: Encode>(w: &mut impl Write, v: &[T]) -> Result<()> {
+    T::encode_seq(v, w)
+}
+
+/// seq_len returns the number of bytes write_seq produces for v.
+#[inline]
+pub fn seq_len<T: Encode>(v: &[T]) -> usize {
+    v.iter().map(T::encoded_len).sum()
+}
+
+/// CountingReader counts the bytes read through it, so that a decoding error can be reported together
+/// with the offset at which it occurred.
+pub struct CountingReader<R: Read> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    #[inline]
+    pub fn new(inner: R) -> Self {
+        Self { inner, position: 0 }
+    }
+
+    /// position returns the number of bytes read so far.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_widths() {
+        let mut r: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert_eq!(r.read_vu32().unwrap(), u32::MAX);
+        assert!(r.is_empty());
+        let mut r: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(r.read_vu64().unwrap(), u64::MAX);
+        assert!(r.is_empty());
+
+        // a 32 bit varint ends after 5 bytes and a 64 bit one after 10, whatever follows.
+        let mut r: &[u8] = &[0x80; 11];
+        assert!(matches!(
+            r.read_vu32().unwrap_err().kind(),
+            ErrorKind::VarintUnterminated
+        ));
+        assert_eq!(r.len(), 6);
+        let mut r: &[u8] = &[0x80; 11];
+        assert!(matches!(
+            r.read_vu64().unwrap_err().kind(),
+            ErrorKind::VarintUnterminated
+        ));
+        assert_eq!(r.len(), 1);
+    }
+
+    #[test]
+    fn zigzag_boundaries() {
+        for (v, bytes) in [
+            (0, &[0x00][..]),
+            (-1, &[0x01]),
+            (1, &[0x02]),
+            (i32::MAX, &[0xfe, 0xff, 0xff, 0xff, 0x0f]),
+            (i32::MIN, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        ] {
+            let mut buf = vec![];
+            buf.write_var_i32(v).unwrap();
+            assert_eq!(buf, bytes, "{}", v);
+            assert_eq!(zigzag_len(v as i64), buf.len());
+            assert_eq!((&mut buf.as_slice()).read_var_i32().unwrap(), v);
+        }
+        for v in [
+            0,
+            -1,
+            1,
+            i32::MIN as i64,
+            i32::MAX as i64,
+            i64::MIN,
+            i64::MAX,
+        ] {
+            let mut buf = vec![];
+            buf.write_var_i64(v).unwrap();
+            assert_eq!(zigzag_len(v), buf.len(), "{}", v);
+            assert_eq!((&mut buf.as_slice()).read_var_i64().unwrap(), v);
+        }
+        assert_eq!(zigzag_len(i64::MIN), 10);
+        assert_eq!(zigzag_len(i64::MAX), 10);
+    }
+
+    #[test]
+    fn varint_overflow() {
+        // the last byte of a full width varint only has room for the remaining 4 and 1 bits.
+        let mut r: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x1f];
+        assert!(matches!(
+            r.read_vu32().unwrap_err().kind(),
+            ErrorKind::VarintOverflow
+        ));
+        let mut r: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert!(matches!(
+            r.read_vu64().unwrap_err().kind(),
+            ErrorKind::VarintOverflow
+        ));
+        // a continuation bit past the width overflows before it is found to be unterminated.
+        let mut r: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(
+            r.read_vu32().unwrap_err().kind(),
+            ErrorKind::VarintOverflow
+        ));
+    }
+
+    #[test]
+    fn strict_varints() {
+        let padded = [0x80, 0x00];
+        assert_eq!((&mut &padded[..]).read_vu32().unwrap(), 0);
+        let strict = Context {
+            strict: true,
+            ..Context::default()
+        };
+        strict.scope(|| {
+            assert!(matches!(
+                (&mut &padded[..]).read_vu32().unwrap_err().kind(),
+                ErrorKind::NonCanonicalVarint
+            ));
+            assert!(matches!(
+                (&mut &[0xff, 0x00][..]).read_vu64().unwrap_err().kind(),
+                ErrorKind::NonCanonicalVarint
+            ));
+            assert_eq!((&mut &[0x00][..]).read_vu32().unwrap(), 0);
+            assert_eq!((&mut &[0x80, 0x01][..]).read_vu32().unwrap(), 128);
+        });
+    }
+
+    #[test]
+    fn vi32_range() {
+        for v in [0, 1, -1, i32::MIN, i32::MAX] {
+            let mut buf = vec![];
+            buf.write_vi32(v).unwrap();
+            assert_eq!((&mut buf.as_slice()).read_vi32().unwrap(), v);
+        }
+        for v in [i32::MAX as i64 + 1, i32::MIN as i64 - 1, i64::MIN] {
+            let mut buf = vec![];
+            buf.write_vi64(v).unwrap();
+            assert!(matches!(
+                (&mut buf.as_slice()).read_vi32().unwrap_err().kind(),
+                ErrorKind::VarintOverflow
+            ));
+        }
+    }
+}
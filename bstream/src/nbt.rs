@@ -0,0 +1,713 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    check_len, read_bytes, Context, Decode, Encode, Error, ErrorKind, LenPrefix, ReaderExt, Result,
+    WriterExt,
+};
+
+/// Encoding is the flavour of NBT being read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// LittleEndian is NBT as stored in worlds, where every number is little endian.
+    LittleEndian,
+    /// Network is NBT as sent in packets, where ints and longs are zigzag varints, string lengths are
+    /// varuint32 and other lengths are zigzag varint32.
+    Network,
+}
+
+/// Compound maps the names of the tags in a compound to the tags.
+pub type Compound = BTreeMap<String, Tag>;
+
+/// Tag is an NBT value of any type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    /// List holds tags that all have the same type. An empty list is written with the type of TAG_End.
+    List(Vec<Tag>),
+    Compound(Compound),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    /// id returns the type of the tag as written before it.
+    pub fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+}
+
+/// read reads a root tag and its name. The root tag is a compound in almost every case.
+pub fn read(r: &mut impl Read, encoding: Encoding) -> Result<(String, Tag)> {
+    let id = r.read_u8()?;
+    let name = read_string(r, encoding)?;
+    let tag = read_payload(r, encoding, id)?;
+    Ok((name, tag))
+}
+
+/// write writes tag as a root tag named name.
+pub fn write(w: &mut impl Write, encoding: Encoding, name: &str, tag: &Tag) -> Result<()> {
+    w.write_u8(tag.id())?;
+    write_string(w, encoding, name)?;
+    write_payload(w, encoding, tag)
+}
+
+/// Frame is a list or compound that read_payload has started but not yet finished reading.
+enum Frame {
+    List {
+        elem: u8,
+        len: usize,
+        list: Vec<Tag>,
+    },
+    Compound {
+        compound: Compound,
+        name: String,
+    },
+}
+
+/// read_payload reads the payload of a tag of type id. Lists and compounds are read with an explicit stack
+/// rather than by recursion, so that nesting is bounded by the max_depth of the current [Context] alone and
+/// not by the size of the stack of the thread.
+fn read_payload(r: &mut impl Read, encoding: Encoding, mut id: u8) -> Result<Tag> {
+    let mut stack = Vec::new();
+    loop {
+        let mut tag = match id {
+            9 => {
+                enter(stack.len())?;
+                let elem = r.read_u8()?;
+                let len = read_len(r, encoding)?;
+                stack.push(Frame::List {
+                    elem,
+                    len,
+                    list: Vec::with_capacity(len.min(1024)),
+                });
+                None
+            }
+            10 => {
+                enter(stack.len())?;
+                stack.push(Frame::Compound {
+                    compound: Compound::new(),
+                    name: String::new(),
+                });
+                None
+            }
+            id => Some(read_value(r, encoding, id)?),
+        };
+        // hand the tag to the list or compound it is in, finishing every list or compound that is complete
+        // with it, until one needs another tag.
+        loop {
+            match stack.last_mut() {
+                None => return Ok(tag.expect("a finished tag is left once the stack is empty")),
+                Some(Frame::List { elem, len, list }) => {
+                    list.extend(tag.take());
+                    if list.len() < *len {
+                        id = *elem;
+                        break;
+                    }
+                }
+                Some(Frame::Compound { compound, name }) => {
+                    if let Some(tag) = tag.take() {
+                        compound.insert(std::mem::take(name), tag);
+                    }
+                    let next = r.read_u8()?;
+                    if next != 0 {
+                        *name = read_string(r, encoding)?;
+                        id = next;
+                        break;
+                    }
+                }
+            }
+            tag = match stack.pop() {
+                Some(Frame::List { list, .. }) => Some(Tag::List(list)),
+                Some(Frame::Compound { compound, .. }) => Some(Tag::Compound(compound)),
+                None => unreachable!(),
+            };
+        }
+    }
+}
+
+/// read_value reads the payload of a tag of type id that holds no other tags.
+fn read_value(r: &mut impl Read, encoding: Encoding, id: u8) -> Result<Tag> {
+    Ok(match id {
+        1 => Tag::Byte(r.read_i8()?),
+        2 => Tag::Short(r.read_i16::<LittleEndian>()?),
+        3 => Tag::Int(read_int(r, encoding)?),
+        4 => Tag::Long(read_long(r, encoding)?),
+        5 => Tag::Float(r.read_f32::<LittleEndian>()?),
+        6 => Tag::Double(r.read_f64::<LittleEndian>()?),
+        7 => {
+            let len = read_len(r, encoding)?;
+            Tag::ByteArray(read_bytes(r, len as u64)?)
+        }
+        8 => Tag::String(read_string(r, encoding)?),
+        11 => {
+            let len = read_len(r, encoding)?;
+            let mut v = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                v.push(read_int(r, encoding)?);
+            }
+            Tag::IntArray(v)
+        }
+        12 => {
+            let len = read_len(r, encoding)?;
+            let mut v = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                v.push(read_long(r, encoding)?);
+            }
+            Tag::LongArray(v)
+        }
+        id => {
+            return Err(Error::new(ErrorKind::InvalidDiscriminant(id as i128)).in_type("Tag"));
+        }
+    })
+}
+
+/// Pending is a list or compound that write_payload is writing the tags of.
+enum Pending<'a> {
+    List {
+        elem: u8,
+        tags: std::slice::Iter<'a, Tag>,
+    },
+    Compound(std::collections::btree_map::Iter<'a, String, Tag>),
+}
+
+/// write_payload writes the payload of tag. Like read_payload, it keeps the lists and compounds it is in on an
+/// explicit stack, so that any tag that can be read can be written again.
+fn write_payload(w: &mut impl Write, encoding: Encoding, mut tag: &Tag) -> Result<()> {
+    let mut stack = Vec::new();
+    loop {
+        match tag {
+            Tag::List(v) => {
+                let elem = v.first().map_or(0, Tag::id);
+                w.write_u8(elem)?;
+                write_len(w, encoding, v.len())?;
+                stack.push(Pending::List {
+                    elem,
+                    tags: v.iter(),
+                });
+            }
+            Tag::Compound(v) => stack.push(Pending::Compound(v.iter())),
+            tag => write_value(w, encoding, tag)?,
+        }
+        // move on to the next tag of the innermost list or compound that has one left, ending the others.
+        loop {
+            match stack.last_mut() {
+                None => return Ok(()),
+                Some(Pending::List { elem, tags }) => {
+                    if let Some(next) = tags.next() {
+                        if next.id() != *elem {
+                            return Err(ErrorKind::UnexpectedTag {
+                                expected: *elem,
+                                found: next.id(),
+                            }
+                            .into());
+                        }
+                        tag = next;
+                        break;
+                    }
+                }
+                Some(Pending::Compound(tags)) => {
+                    if let Some((name, next)) = tags.next() {
+                        w.write_u8(next.id())?;
+                        write_string(w, encoding, name)?;
+                        tag = next;
+                        break;
+                    }
+                    w.write_u8(0)?;
+                }
+            }
+            stack.pop();
+        }
+    }
+}
+
+/// write_value writes the payload of a tag that holds no other tags.
+fn write_value(w: &mut impl Write, encoding: Encoding, tag: &Tag) -> Result<()> {
+    match tag {
+        Tag::Byte(v) => w.write_i8(*v)?,
+        Tag::Short(v) => w.write_i16::<LittleEndian>(*v)?,
+        Tag::Int(v) => write_int(w, encoding, *v)?,
+        Tag::Long(v) => write_long(w, encoding, *v)?,
+        Tag::Float(v) => w.write_f32::<LittleEndian>(*v)?,
+        Tag::Double(v) => w.write_f64::<LittleEndian>(*v)?,
+        Tag::ByteArray(v) => {
+            write_len(w, encoding, v.len())?;
+            w.write_all(v)?;
+        }
+        Tag::String(v) => write_string(w, encoding, v)?,
+        Tag::IntArray(v) => {
+            write_len(w, encoding, v.len())?;
+            for v in v {
+                write_int(w, encoding, *v)?;
+            }
+        }
+        Tag::LongArray(v) => {
+            write_len(w, encoding, v.len())?;
+            for v in v {
+                write_long(w, encoding, *v)?;
+            }
+        }
+        Tag::List(_) | Tag::Compound(_) => {
+            unreachable!("lists and compounds are written by write_payload")
+        }
+    }
+    Ok(())
+}
+
+/// enter checks that a list or compound may be opened inside depth others, failing if that exceeds the
+/// max_depth of the current [Context].
+#[inline]
+fn enter(depth: usize) -> Result<()> {
+    let limit = Context::current().max_depth;
+    if depth >= limit {
+        return Err(ErrorKind::DepthLimitExceeded(limit).into());
+    }
+    Ok(())
+}
+
+#[inline]
+fn read_int(r: &mut impl Read, encoding: Encoding) -> Result<i32> {
+    match encoding {
+        Encoding::LittleEndian => Ok(r.read_i32::<LittleEndian>()?),
+        Encoding::Network => r.read_var_i32(),
+    }
+}
+
+#[inline]
+fn write_int(w: &mut impl Write, encoding: Encoding, v: i32) -> Result<()> {
+    match encoding {
+        Encoding::LittleEndian => w.write_i32::<LittleEndian>(v)?,
+        Encoding::Network => w.write_var_i32(v)?,
+    }
+    Ok(())
+}
+
+#[inline]
+fn read_long(r: &mut impl Read, encoding: Encoding) -> Result<i64> {
+    match encoding {
+        Encoding::LittleEndian => Ok(r.read_i64::<LittleEndian>()?),
+        Encoding::Network => r.read_var_i64(),
+    }
+}
+
+#[inline]
+fn write_long(w: &mut impl Write, encoding: Encoding, v: i64) -> Result<()> {
+    match encoding {
+        Encoding::LittleEndian => w.write_i64::<LittleEndian>(v)?,
+        Encoding::Network => w.write_var_i64(v)?,
+    }
+    Ok(())
+}
+
+/// read_len reads the length of a list or an array, after checking it with [check_len].
+#[inline]
+fn read_len(r: &mut impl Read, encoding: Encoding) -> Result<usize> {
+    let len = match encoding {
+        Encoding::LittleEndian => LenPrefix::I32.read(r)?,
+        Encoding::Network => {
+            let len = r.read_var_i32()?;
+            if len < 0 {
+                return Err(ErrorKind::NegativeLength(len as i64).into());
+            }
+            len as u64
+        }
+    };
+    check_len(len)
+}
+
+#[inline]
+fn write_len(w: &mut impl Write, encoding: Encoding, len: usize) -> Result<()> {
+    match encoding {
+        Encoding::LittleEndian => LenPrefix::I32.write(w, len),
+        Encoding::Network => {
+            let len = i32::try_from(len).map_err(|_| ErrorKind::LengthLimitExceeded {
+                len: len as u64,
+                limit: i32::MAX as u64,
+            })?;
+            Ok(w.write_var_i32(len)?)
+        }
+    }
+}
+
+#[inline]
+fn read_string(r: &mut impl Read, encoding: Encoding) -> Result<String> {
+    match encoding {
+        Encoding::LittleEndian => r.read_string_u16(),
+        Encoding::Network => r.read_string(),
+    }
+}
+
+#[inline]
+fn write_string(w: &mut impl Write, encoding: Encoding, v: &str) -> Result<()> {
+    match encoding {
+        Encoding::LittleEndian => w.write_string_u16(v),
+        Encoding::Network => w.write_string(v),
+    }
+}
+
+/// FromTag is implemented by types that can be converted from a tag, such as the typed form of a compound.
+pub trait FromTag: Sized {
+    fn from_tag(tag: Tag) -> Result<Self>;
+}
+
+/// ToTag is implemented by types that can be converted to a tag.
+pub trait ToTag {
+    fn to_tag(&self) -> Tag;
+}
+
+/// get converts the tag named name in compound to T, which is how typed compounds implement FromTag.
+pub fn get<T: FromTag>(compound: &Compound, name: &'static str) -> Result<T> {
+    let tag = compound
+        .get(name)
+        .ok_or_else(|| Error::new(ErrorKind::MissingTag).in_field("Compound", name))?;
+    T::from_tag(tag.clone()).map_err(|e| e.in_field("Compound", name))
+}
+
+macro_rules! impl_tag {
+    ($($typ:ty, $variant:ident, $id:expr;)*) => {
+        $(
+            impl FromTag for $typ {
+                #[inline]
+                fn from_tag(tag: Tag) -> Result<Self> {
+                    match tag {
+                        Tag::$variant(v) => Ok(v),
+                        tag => Err(ErrorKind::UnexpectedTag {
+                            expected: $id,
+                            found: tag.id(),
+                        }
+                        .into()),
+                    }
+                }
+            }
+
+            impl ToTag for $typ {
+                #[inline]
+                fn to_tag(&self) -> Tag {
+                    Tag::$variant(self.clone())
+                }
+            }
+        )*
+    };
+}
+
+impl_tag! {
+    i8, Byte, 1;
+    i16, Short, 2;
+    i32, Int, 3;
+    i64, Long, 4;
+    f32, Float, 5;
+    f64, Double, 6;
+    String, String, 8;
+}
+
+/// bool is stored as a byte that is 1 if it is true.
+impl FromTag for bool {
+    #[inline]
+    fn from_tag(tag: Tag) -> Result<Self> {
+        i8::from_tag(tag).map(|v| v != 0)
+    }
+}
+
+impl ToTag for bool {
+    #[inline]
+    fn to_tag(&self) -> Tag {
+        Tag::Byte(*self as i8)
+    }
+}
+
+impl FromTag for Tag {
+    #[inline]
+    fn from_tag(tag: Tag) -> Result<Self> {
+        Ok(tag)
+    }
+}
+
+impl ToTag for Tag {
+    #[inline]
+    fn to_tag(&self) -> Tag {
+        self.clone()
+    }
+}
+
+/// Vec is stored as a list.
+impl<T: FromTag> FromTag for Vec<T> {
+    fn from_tag(tag: Tag) -> Result<Self> {
+        match tag {
+            Tag::List(v) => v.into_iter().map(T::from_tag).collect(),
+            tag => Err(ErrorKind::UnexpectedTag {
+                expected: 9,
+                found: tag.id(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl<T: ToTag> ToTag for Vec<T> {
+    fn to_tag(&self) -> Tag {
+        Tag::List(self.iter().map(T::to_tag).collect())
+    }
+}
+
+/// BTreeMap is stored as a compound, and is a Compound itself if T is Tag.
+impl<T: FromTag> FromTag for BTreeMap<String, T> {
+    fn from_tag(tag: Tag) -> Result<Self> {
+        match tag {
+            Tag::Compound(v) => v
+                .into_iter()
+                .map(|(name, tag)| Ok((name, T::from_tag(tag)?)))
+                .collect(),
+            tag => Err(ErrorKind::UnexpectedTag {
+                expected: 10,
+                found: tag.id(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl<T: ToTag> ToTag for BTreeMap<String, T> {
+    fn to_tag(&self) -> Tag {
+        Tag::Compound(
+            self.iter()
+                .map(|(name, v)| (name.clone(), v.to_tag()))
+                .collect(),
+        )
+    }
+}
+
+macro_rules! impl_root {
+    ($($name:ident, $encoding:ident;)*) => {
+        $(
+            impl<T: FromTag> Decode for $name<T> {
+                fn decode(out: &mut impl Read) -> Result<Self> {
+                    let (_, tag) = read(out, Encoding::$encoding)?;
+                    Ok(Self(T::from_tag(tag)?))
+                }
+            }
+
+            impl<T: ToTag> Encode for $name<T> {
+                fn encode(&self, out: &mut impl Write) -> Result<()> {
+                    write(out, Encoding::$encoding, "", &self.0.to_tag())
+                }
+            }
+        )*
+    };
+}
+
+/// NetworkNbt is a root tag with an empty name in the network encoding, as embedded in packets. T is usually
+/// a Compound or a typed compound.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkNbt<T = Compound>(pub T);
+
+/// LittleEndianNbt is a root tag with an empty name in the little endian encoding.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LittleEndianNbt<T = Compound>(pub T);
+
+impl_root! {
+    NetworkNbt, Network;
+    LittleEndianNbt, LittleEndian;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::DEFAULT_MAX_DEPTH;
+
+    use super::*;
+
+    fn compound() -> Compound {
+        let mut inner = Compound::new();
+        inner.insert("float".into(), Tag::Float(1.5));
+        inner.insert("empty".into(), Tag::List(vec![]));
+        let mut c = Compound::new();
+        c.insert("byte".into(), Tag::Byte(-1));
+        c.insert("short".into(), Tag::Short(300));
+        c.insert("int".into(), Tag::Int(-5));
+        c.insert("long".into(), Tag::Long(i64::MIN));
+        c.insert("double".into(), Tag::Double(-0.25));
+        c.insert("bytes".into(), Tag::ByteArray(vec![1, 2, 3]));
+        c.insert("string".into(), Tag::String("hi".into()));
+        c.insert("list".into(), Tag::List(vec![Tag::Long(1), Tag::Long(2)]));
+        c.insert(
+            "compounds".into(),
+            Tag::List(vec![Tag::Compound(inner.clone())]),
+        );
+        c.insert("compound".into(), Tag::Compound(inner));
+        c.insert("ints".into(), Tag::IntArray(vec![1, -2]));
+        c.insert("longs".into(), Tag::LongArray(vec![i64::MAX]));
+        c
+    }
+
+    /// nested returns depth lists nested in each other around an empty compound, which makes depth + 1
+    /// lists and compounds in total.
+    fn nested(depth: usize) -> Tag {
+        let mut tag = Tag::Compound(Compound::new());
+        for _ in 0..depth {
+            tag = Tag::List(vec![tag]);
+        }
+        tag
+    }
+
+    #[test]
+    fn roundtrip() {
+        for encoding in [Encoding::Network, Encoding::LittleEndian] {
+            let mut buf = vec![];
+            write(&mut buf, encoding, "root", &Tag::Compound(compound())).unwrap();
+            let (name, tag) = read(&mut buf.as_slice(), encoding).unwrap();
+            assert_eq!(name, "root");
+            assert_eq!(tag, Tag::Compound(compound()));
+        }
+    }
+
+    #[test]
+    fn encodings() {
+        let mut c = Compound::new();
+        c.insert("a".into(), Tag::Int(-1));
+        let mut buf = vec![];
+        write(&mut buf, Encoding::Network, "", &Tag::Compound(c.clone())).unwrap();
+        assert_eq!(buf, [10, 0, 3, 1, b'a', 1, 0]);
+        let mut buf = vec![];
+        write(&mut buf, Encoding::LittleEndian, "", &Tag::Compound(c)).unwrap();
+        assert_eq!(buf, [10, 0, 0, 3, 1, 0, b'a', 0xff, 0xff, 0xff, 0xff, 0]);
+    }
+
+    #[test]
+    fn roots() {
+        let v = NetworkNbt(compound());
+        let mut buf = vec![];
+        v.encode(&mut buf).unwrap();
+        assert_eq!(buf.len(), v.encoded_len());
+        assert_eq!(
+            NetworkNbt::<Compound>::decode(&mut buf.as_slice()).unwrap(),
+            v
+        );
+
+        let v = LittleEndianNbt(compound());
+        let mut buf = vec![];
+        v.encode(&mut buf).unwrap();
+        assert_eq!(
+            LittleEndianNbt::<Compound>::decode(&mut buf.as_slice()).unwrap(),
+            v
+        );
+    }
+
+    #[test]
+    fn typed_compounds() {
+        let tag = Tag::Compound(compound());
+        let c = Compound::from_tag(tag.clone()).unwrap();
+        assert_eq!(get::<i32>(&c, "int").unwrap(), -5);
+        assert_eq!(get::<Vec<i64>>(&c, "list").unwrap(), [1, 2]);
+        assert!(get::<bool>(&c, "byte").unwrap());
+        let e = get::<i32>(&c, "missing").unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::MissingTag));
+        let e = get::<i32>(&c, "string").unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            ErrorKind::UnexpectedTag {
+                expected: 3,
+                found: 8
+            }
+        ));
+        assert_eq!(c.to_tag(), tag);
+    }
+
+    #[test]
+    fn mixed_lists_are_rejected() {
+        let list = Tag::List(vec![Tag::Int(1), Tag::Byte(1)]);
+        for encoding in [Encoding::Network, Encoding::LittleEndian] {
+            let e = write(&mut vec![], encoding, "", &list).unwrap_err();
+            assert!(matches!(
+                e.kind(),
+                ErrorKind::UnexpectedTag {
+                    expected: 3,
+                    found: 1
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        let e = read(&mut &[9u8, 0, 3, 1][..], Encoding::Network).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::NegativeLength(-1)));
+        let e = read(&mut &[10u8, 0, 13, 0][..], Encoding::Network).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::InvalidDiscriminant(13)));
+        // a compound without its end tag.
+        assert!(read(&mut &[10u8, 0, 1, 0, 1][..], Encoding::Network).is_err());
+    }
+
+    #[test]
+    fn depth_limit() {
+        let mut buf = vec![];
+        write(&mut buf, Encoding::Network, "", &nested(10)).unwrap();
+        let e = Context {
+            max_depth: 10,
+            ..Context::current()
+        }
+        .scope(|| read(&mut buf.as_slice(), Encoding::Network))
+        .unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::DepthLimitExceeded(10)));
+        let limit = Context {
+            max_depth: 11,
+            ..Context::current()
+        };
+        assert_eq!(
+            limit
+                .scope(|| read(&mut buf.as_slice(), Encoding::Network))
+                .unwrap()
+                .1,
+            nested(10)
+        );
+    }
+
+    #[test]
+    fn nesting_bomb() {
+        // compounds nested one in another, without ever being ended, cost two bytes a level.
+        let mut bomb = vec![10, 0];
+        for _ in 0..100_000 {
+            bomb.extend([10, 0]);
+        }
+        // the default limit must hold on a small stack in every build profile.
+        let nested = nested(DEFAULT_MAX_DEPTH - 1);
+        thread::Builder::new()
+            .stack_size(2 << 20)
+            .spawn(move || {
+                let e = read(&mut bomb.as_slice(), Encoding::Network).unwrap_err();
+                assert!(matches!(
+                    e.kind(),
+                    ErrorKind::DepthLimitExceeded(DEFAULT_MAX_DEPTH)
+                ));
+
+                let mut buf = vec![];
+                write(&mut buf, Encoding::Network, "", &nested).unwrap();
+                assert!(read(&mut buf.as_slice(), Encoding::Network).is_ok());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
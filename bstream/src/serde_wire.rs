@@ -0,0 +1,810 @@
+use std::fmt;
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{check_len, read_bytes, Error, ErrorKind, LenPrefix, ReaderExt, Result, WriterExt};
+
+const VARINT: &str = "$bstream::Varint";
+const BIG_ENDIAN: &str = "$bstream::BigEndian";
+
+/// Int is the encoding of the next number, as chosen by the field wrappers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Int {
+    #[default]
+    LittleEndian,
+    BigEndian,
+    Varint,
+}
+
+#[inline]
+fn unsupported(int: Int) -> Error {
+    ErrorKind::Message(
+        match int {
+            Int::Varint => "Varint is only supported on u32, u64, i32 and i64",
+            _ => "BigEndian is only supported on numbers",
+        }
+        .into(),
+    )
+    .into()
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        ErrorKind::Message(msg.to_string()).into()
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        ErrorKind::Message(msg.to_string()).into()
+    }
+}
+
+macro_rules! field_wrapper {
+    ($(#[$doc:meta])* $name:ident, $marker:expr) => {
+        $(#[$doc])*
+        pub mod $name {
+            use std::marker::PhantomData;
+
+            use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+            pub fn serialize<T: Serialize, S: Serializer>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_newtype_struct($marker, v)
+            }
+
+            pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(d: D) -> Result<T, D::Error> {
+                d.deserialize_newtype_struct($marker, super::Wrapped(PhantomData))
+            }
+        }
+    };
+}
+
+field_wrapper! {
+    /// varint encodes a u32, u64, i32 or i64 field as a varint, the same as `#[Varint]` does in the derive. Signed
+    /// numbers are zigzag encoded. Use it with `#[serde(with = "bstream::serde_wire::varint")]`.
+    varint, super::VARINT
+}
+
+field_wrapper! {
+    /// big_endian encodes a number field in big endian, the same as `#[BigEndian]` does in the derive. Use it with
+    /// `#[serde(with = "bstream::serde_wire::big_endian")]`.
+    big_endian, super::BIG_ENDIAN
+}
+
+/// Wrapped deserializes the value inside a field wrapper. Other formats see the wrapper as a newtype struct, so
+/// it is transparent to them.
+struct Wrapped<T>(PhantomData<T>);
+
+impl<'de, T: de::Deserialize<'de>> Visitor<'de> for Wrapped<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number")
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        d: D,
+    ) -> std::result::Result<T, D::Error> {
+        T::deserialize(d)
+    }
+}
+
+macro_rules! impl_wrapper {
+    ($($name:ty, $inner:ty, $marker:expr $(, $generic:ident)?;)*) => {
+        $(
+            impl<$($generic: Serialize)?> Serialize for $name {
+                fn serialize<S: ser::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+                    s.serialize_newtype_struct($marker, &self.0)
+                }
+            }
+
+            impl<'de, $($generic: de::Deserialize<'de>)?> de::Deserialize<'de> for $name {
+                fn deserialize<D: de::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+                    d.deserialize_newtype_struct($marker, Wrapped::<$inner>(PhantomData))
+                        .map(Self)
+                }
+            }
+        )*
+    };
+}
+
+// the typed wrappers are encoded the same as fields using the field wrappers.
+impl_wrapper! {
+    crate::VarU32, u32, VARINT;
+    crate::VarI32, i32, VARINT;
+    crate::VarU64, u64, VARINT;
+    crate::VarI64, i64, VARINT;
+    crate::BigEndian<T>, T, BIG_ENDIAN, T;
+}
+
+impl<T: Serialize> Serialize for crate::LittleEndian<T> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        self.0.serialize(s)
+    }
+}
+
+impl<'de, T: de::Deserialize<'de>> de::Deserialize<'de> for crate::LittleEndian<T> {
+    fn deserialize<D: de::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        T::deserialize(d).map(Self)
+    }
+}
+
+/// Number is a number that the field wrappers may change the encoding of.
+trait Number: Sized {
+    fn write(self, w: &mut impl Write, int: Int) -> Result<()>;
+    fn read(r: &mut impl Read, int: Int) -> Result<Self>;
+}
+
+macro_rules! impl_number {
+    ($($typ:ty, $read:ident, $write:ident $(, $read_var:ident, $write_var:ident)?;)*) => {
+        $(
+            impl Number for $typ {
+                #[inline]
+                fn write(self, w: &mut impl Write, int: Int) -> Result<()> {
+                    match int {
+                        Int::LittleEndian => w.$write::<LittleEndian>(self)?,
+                        Int::BigEndian => w.$write::<BigEndian>(self)?,
+                        $(Int::Varint => w.$write_var(self)?,)?
+                        #[allow(unreachable_patterns)]
+                        _ => return Err(unsupported(int)),
+                    }
+                    Ok(())
+                }
+
+                #[inline]
+                fn read(r: &mut impl Read, int: Int) -> Result<Self> {
+                    Ok(match int {
+                        Int::LittleEndian => r.$read::<LittleEndian>()?,
+                        Int::BigEndian => r.$read::<BigEndian>()?,
+                        $(Int::Varint => r.$read_var()?,)?
+                        #[allow(unreachable_patterns)]
+                        _ => return Err(unsupported(int)),
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_number! {
+    u16, read_u16, write_u16;
+    u32, read_u32, write_u32, read_vu32, write_vu32;
+    u64, read_u64, write_u64, read_vu64, write_vu64;
+    u128, read_u128, write_u128;
+    i16, read_i16, write_i16;
+    i32, read_i32, write_i32, read_var_i32, write_var_i32;
+    i64, read_i64, write_i64, read_var_i64, write_var_i64;
+    i128, read_i128, write_i128;
+    f32, read_f32, write_f32;
+    f64, read_f64, write_f64;
+}
+
+/// to_writer serializes v into w with a [Serializer].
+pub fn to_writer<T: Serialize + ?Sized>(w: impl Write, v: &T) -> Result<()> {
+    v.serialize(&mut Serializer::new(w))
+}
+
+/// to_vec serializes v with a [Serializer] and returns the bytes written.
+pub fn to_vec<T: Serialize + ?Sized>(v: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    to_writer(&mut buf, v)?;
+    Ok(buf)
+}
+
+/// from_reader deserializes a T from r with a [Deserializer].
+pub fn from_reader<T: DeserializeOwned>(r: impl Read) -> Result<T> {
+    T::deserialize(&mut Deserializer::new(r))
+}
+
+/// from_slice deserializes a T from the start of buf with a [Deserializer]. Bytes after the value are ignored.
+pub fn from_slice<T: DeserializeOwned>(buf: &[u8]) -> Result<T> {
+    from_reader(buf)
+}
+
+/// Serializer writes values in the encodings ReaderExt and WriterExt use, so that a type deriving Serialize is
+/// written like the same type deriving BinaryStream:
+///
+/// - numbers are little endian unless a field uses [varint] or [big_endian], and bool is a single byte.
+/// - strings, bytes, sequences and maps are prefixed with their length as a varuint32.
+/// - Option is preceded by a bool that is true if the value is present.
+/// - structs and tuples are their fields in order, without any prefix.
+/// - enum variants are prefixed with their index as a varuint32.
+/// - char is its code point as a little endian u32.
+pub struct Serializer<W> {
+    w: W,
+    int: Int,
+}
+
+impl<W: Write> Serializer<W> {
+    #[inline]
+    pub fn new(w: W) -> Self {
+        Self {
+            w,
+            int: Int::default(),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    /// int takes the encoding chosen for the next number.
+    #[inline]
+    fn int(&mut self) -> Int {
+        std::mem::take(&mut self.int)
+    }
+
+    /// plain fails if a field wrapper was used on a value that is not a number.
+    #[inline]
+    fn plain(&mut self) -> Result<()> {
+        match self.int() {
+            Int::LittleEndian => Ok(()),
+            int => Err(unsupported(int)),
+        }
+    }
+
+    /// byte fails if a varint was requested for a single byte, for which endianness does not matter.
+    #[inline]
+    fn byte(&mut self) -> Result<()> {
+        match self.int() {
+            Int::Varint => Err(unsupported(Int::Varint)),
+            _ => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn number(&mut self, v: impl Number) -> Result<()> {
+        let int = self.int();
+        v.write(&mut self.w, int)
+    }
+}
+
+impl<W: Write> ser::Serializer for &mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.plain()?;
+        Ok(self.w.write_bool(v)?)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.byte()?;
+        Ok(self.w.write_i8(v)?)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.byte()?;
+        Ok(self.w.write_u8(v)?)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.number(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.plain()?;
+        Ok(self.w.write_u32::<LittleEndian>(v as u32)?)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.plain()?;
+        self.w.write_string(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.plain()?;
+        LenPrefix::Vu32.write(&mut self.w, v.len())?;
+        Ok(self.w.write_all(v)?)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.plain()?;
+        Ok(self.w.write_bool(false)?)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, v: &T) -> Result<()> {
+        self.plain()?;
+        self.w.write_bool(true)?;
+        v.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.plain()
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<()> {
+        self.plain()
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, index: u32, _: &'static str) -> Result<()> {
+        self.plain()?;
+        Ok(self.w.write_vu32(index)?)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        v: &T,
+    ) -> Result<()> {
+        match name {
+            VARINT => self.int = Int::Varint,
+            BIG_ENDIAN => self.int = Int::BigEndian,
+            _ => self.plain()?,
+        }
+        v.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        index: u32,
+        _: &'static str,
+        v: &T,
+    ) -> Result<()> {
+        self.plain()?;
+        self.w.write_vu32(index)?;
+        v.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self> {
+        self.plain()?;
+        let len = len.ok_or_else(|| ErrorKind::Message("sequence length must be known".into()))?;
+        LenPrefix::Vu32.write(&mut self.w, len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self> {
+        self.plain()?;
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self> {
+        self.plain()?;
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        index: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self> {
+        self.plain()?;
+        self.w.write_vu32(index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self> {
+        self.serialize_seq(len)
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self> {
+        self.plain()?;
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        index: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self> {
+        self.plain()?;
+        self.w.write_vu32(index)?;
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+macro_rules! impl_compound {
+    ($($trait:ident, $fn:ident $(, $key:ty)?;)*) => {
+        $(
+            impl<W: Write> ser::$trait for &mut Serializer<W> {
+                type Ok = ();
+                type Error = Error;
+
+                fn $fn<T: Serialize + ?Sized>(&mut self, $(_: $key,)? v: &T) -> Result<()> {
+                    v.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<()> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_compound! {
+    SerializeSeq, serialize_element;
+    SerializeTuple, serialize_element;
+    SerializeTupleStruct, serialize_field;
+    SerializeTupleVariant, serialize_field;
+    SerializeStruct, serialize_field, &'static str;
+    SerializeStructVariant, serialize_field, &'static str;
+}
+
+impl<W: Write> ser::SerializeMap for &mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<()> {
+        v.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Deserializer reads values written by a [Serializer]. The format is not self-describing, so types that need
+/// deserialize_any, such as untagged enums, are not supported.
+pub struct Deserializer<R> {
+    r: R,
+    int: Int,
+}
+
+impl<R: Read> Deserializer<R> {
+    #[inline]
+    pub fn new(r: R) -> Self {
+        Self {
+            r,
+            int: Int::default(),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    #[inline]
+    fn int(&mut self) -> Int {
+        std::mem::take(&mut self.int)
+    }
+
+    #[inline]
+    fn plain(&mut self) -> Result<()> {
+        match self.int() {
+            Int::LittleEndian => Ok(()),
+            int => Err(unsupported(int)),
+        }
+    }
+
+    #[inline]
+    fn byte(&mut self) -> Result<()> {
+        match self.int() {
+            Int::Varint => Err(unsupported(Int::Varint)),
+            _ => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn number<T: Number>(&mut self) -> Result<T> {
+        let int = self.int();
+        T::read(&mut self.r, int)
+    }
+
+    /// len reads a varuint32 length prefix, after checking it with [check_len].
+    #[inline]
+    fn len(&mut self) -> Result<usize> {
+        check_len(LenPrefix::Vu32.read(&mut self.r)?)
+    }
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value> {
+        Err(ErrorKind::Message("bstream's wire format is not self-describing".into()).into())
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        visitor.visit_bool(self.r.read_bool()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.byte()?;
+        visitor.visit_i8(self.r.read_i8()?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(self.number()?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(self.number()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(self.number()?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i128(self.number()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.byte()?;
+        visitor.visit_u8(self.r.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(self.number()?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(self.number()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.number()?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u128(self.number()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(self.number()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(self.number()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        let v = self.r.read_u32::<LittleEndian>()?;
+        let c = char::from_u32(v).ok_or(ErrorKind::InvalidDiscriminant(v as i128))?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        visitor.visit_string(self.r.read_string()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        let len = LenPrefix::Vu32.read(&mut self.r)?;
+        visitor.visit_byte_buf(read_bytes(&mut self.r, len)?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        if self.r.read_bool()? {
+            return visitor.visit_some(self);
+        }
+        visitor.visit_none()
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        match name {
+            VARINT => self.int = Int::Varint,
+            BIG_ENDIAN => self.int = Int::BigEndian,
+            _ => self.plain()?,
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        let len = self.len()?;
+        visitor.visit_seq(Access { de: self, len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        visitor.visit_seq(Access { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.plain()?;
+        let len = self.len()?;
+        visitor.visit_map(Access { de: self, len })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.plain()?;
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Access hands out the len elements of a sequence, map, tuple or struct.
+struct Access<'a, R> {
+    de: &'a mut Deserializer<R>,
+    len: usize,
+}
+
+impl<'de, R: Read> de::SeqAccess<'de> for Access<'_, R> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de, R: Read> de::MapAccess<'de> for Access<'_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de, R: Read> de::EnumAccess<'de> for &mut Deserializer<R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index = self.r.read_vu32()?;
+        let v = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(index))?;
+        Ok((v, self))
+    }
+}
+
+impl<'de, R: Read> de::VariantAccess<'de> for &mut Deserializer<R> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
@@ -0,0 +1,108 @@
+use std::borrow::Cow;
+use std::io;
+use std::io::{Read, Write};
+
+use crate::{check_len, Decode, Encode, ErrorKind, LenPrefix, Result};
+
+/// SliceReader reads from a byte slice it borrows. Besides implementing Read, so that it works with
+/// ReaderExt and Decode like any reader, it can hand out sub-slices of its input without copying them.
+#[derive(Debug, Clone)]
+pub struct SliceReader<'a> {
+    buf: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    #[inline]
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, position: 0 }
+    }
+
+    /// position returns the number of bytes read so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// remaining returns the bytes that have not been read yet.
+    #[inline]
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.position..]
+    }
+
+    /// read_slice reads the next len bytes and returns them borrowed from the input.
+    #[inline]
+    pub fn read_slice(&mut self, len: usize) -> Result<&'a [u8]> {
+        let remaining = self.remaining();
+        if remaining.len() < len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        self.position += len;
+        Ok(&remaining[..len])
+    }
+}
+
+impl Read for SliceReader<'_> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.remaining().read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let src = self.read_slice(buf.len())?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+}
+
+/// Vu32LenBytes is encoded like Vu32LenByteSlice, but may borrow its payload from the input it was read from
+/// instead of owning a copy of it. It only borrows when read with [Vu32LenBytes::read_borrowed], since Decode,
+/// which derived types use for their fields, reads from any reader and so always copies.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Vu32LenBytes<'a>(pub Cow<'a, [u8]>);
+
+impl<'a> Vu32LenBytes<'a> {
+    /// read_borrowed reads the payload without copying it out of r.
+    #[inline]
+    pub fn read_borrowed(r: &mut SliceReader<'a>) -> Result<Self> {
+        let len = check_len(LenPrefix::Vu32.read(r)?)?;
+        Ok(Self(Cow::Borrowed(r.read_slice(len)?)))
+    }
+
+    #[inline]
+    pub fn into_owned(self) -> Vu32LenBytes<'static> {
+        Vu32LenBytes(Cow::Owned(self.0.into_owned()))
+    }
+}
+
+impl Decode for Vu32LenBytes<'_> {
+    /// decode copies the payload, since a generic reader has nothing to borrow from. Use read_borrowed to
+    /// avoid the copy.
+    #[inline]
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        let len = LenPrefix::Vu32.read(out)?;
+        Ok(Self(Cow::Owned(crate::read_bytes(out, len)?)))
+    }
+
+    #[inline]
+    fn skip(out: &mut impl Read) -> Result<()> {
+        let len = LenPrefix::Vu32.read(out)?;
+        crate::skip_bytes(out, len)
+    }
+}
+
+impl Encode for Vu32LenBytes<'_> {
+    #[inline]
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        LenPrefix::Vu32.write(out, self.0.len())?;
+        Ok(out.write_all(&self.0)?)
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        LenPrefix::Vu32.encoded_len(self.0.len()) + self.0.len()
+    }
+}
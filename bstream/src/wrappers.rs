@@ -0,0 +1,123 @@
+use std::io::{Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use crate::{varint_len, zigzag_len, Decode, Encode, ReaderExt, Result, WriterExt};
+
+macro_rules! impl_varint {
+    ($($(#[$doc:meta])* $name:ident, $typ:ty, $read:ident, $write:ident, $len:expr;)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            pub struct $name(pub $typ);
+
+            impl From<$typ> for $name {
+                #[inline]
+                fn from(v: $typ) -> Self {
+                    Self(v)
+                }
+            }
+
+            impl From<$name> for $typ {
+                #[inline]
+                fn from(v: $name) -> Self {
+                    v.0
+                }
+            }
+
+            impl Decode for $name {
+                #[inline]
+                fn decode(out: &mut impl Read) -> Result<Self> {
+                    Ok(Self(out.$read()?))
+                }
+            }
+
+            impl Encode for $name {
+                #[inline]
+                fn encode(&self, out: &mut impl Write) -> Result<()> {
+                    Ok(out.$write(self.0)?)
+                }
+
+                #[inline]
+                fn encoded_len(&self) -> usize {
+                    $len(self.0)
+                }
+            }
+        )*
+    };
+}
+
+impl_varint! {
+    /// VarU32 is a u32 encoded as a varuint32.
+    VarU32, u32, read_vu32, write_vu32, |v: u32| varint_len(v as u64);
+    /// VarI32 is an i32 encoded as a zigzag varint32, like `#[Varint] i32` fields.
+    VarI32, i32, read_var_i32, write_var_i32, |v: i32| zigzag_len(v as i64);
+    /// VarU64 is a u64 encoded as a varuint64.
+    VarU64, u64, read_vu64, write_vu64, varint_len;
+    /// VarI64 is an i64 encoded as a zigzag varint64, like `#[Varint] i64` fields.
+    VarI64, i64, read_var_i64, write_var_i64, zigzag_len;
+}
+
+/// BigEndian is a number encoded in big endian. Unlike the `#[BigEndian]` field attribute it is part of the
+/// type, so it also works in a Vec or through a type alias.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BigEndian<T>(pub T);
+
+/// LittleEndian is a number encoded in little endian, which is also how a bare number is encoded. It exists to
+/// spell out the byte order where it matters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LittleEndian<T>(pub T);
+
+macro_rules! impl_endian {
+    ($($typ:ty, $read:ident, $write:ident;)*) => {
+        $(
+            impl_endian!(@impl BigEndian, $typ, $read, $write);
+            impl_endian!(@impl LittleEndian, $typ, $read, $write);
+        )*
+    };
+    (@impl $name:ident, $typ:ty, $read:ident, $write:ident) => {
+        impl From<$typ> for $name<$typ> {
+            #[inline]
+            fn from(v: $typ) -> Self {
+                Self(v)
+            }
+        }
+
+        impl From<$name<$typ>> for $typ {
+            #[inline]
+            fn from(v: $name<$typ>) -> Self {
+                v.0
+            }
+        }
+
+        impl Decode for $name<$typ> {
+            #[inline]
+            fn decode(out: &mut impl Read) -> Result<Self> {
+                Ok(Self(out.$read::<byteorder::$name>()?))
+            }
+        }
+
+        impl Encode for $name<$typ> {
+            #[inline]
+            fn encode(&self, out: &mut impl Write) -> Result<()> {
+                Ok(out.$write::<byteorder::$name>(self.0)?)
+            }
+
+            #[inline]
+            fn encoded_len(&self) -> usize {
+                ::std::mem::size_of::<$typ>()
+            }
+        }
+    };
+}
+
+impl_endian! {
+    u16, read_u16, write_u16;
+    u32, read_u32, write_u32;
+    u64, read_u64, write_u64;
+    i16, read_i16, write_i16;
+    i32, read_i32, write_i32;
+    i64, read_i64, write_i64;
+    f32, read_f32, write_f32;
+    f64, read_f64, write_f64;
+}
@@ -42,12 +42,12 @@ fn test_protocol() {
     };
     println!("{:?}", &pk);
 
-    DefaultProtocol::write_packet(&mut f, &pk).unwrap();
+    let protocol: Box<dyn Protocol> = Box::<VersionedProtocol>::default();
+    protocol.write_packet(&mut f, &pk).unwrap();
 
     f.rewind().unwrap();
 
-    let pool = DefaultProtocol::pool();
-    let some = DefaultProtocol::read_packet(&pool, &mut f).unwrap();
+    let (_, some) = protocol.read_packet(&mut f).unwrap();
 
     match some {
         RequestNetworkSettings(pk) => {
@@ -0,0 +1,171 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use bstream::{Error, ErrorKind, LenPrefix, Result, SliceReader, Vu32LenBytes};
+use byteorder::WriteBytesExt;
+
+use crate::minecraft::packets::PacketKind;
+use crate::minecraft::{LazyPacket, PacketHeader, Protocol};
+
+/// GAME_PACKET_ID is the id of the RakNet packet that carries a batch.
+pub const GAME_PACKET_ID: u8 = 0xfe;
+
+/// MAX_BATCH_PACKETS is the most packets a single batch may hold, which is the limit the vanilla server uses.
+pub const MAX_BATCH_PACKETS: usize = 812;
+
+/// Batch is the payload of a game packet: any number of packets, each prefixed with its length as a
+/// varuint32. Because every packet carries its own length, a packet that fails to decode can be skipped
+/// without losing track of the packets after it.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default)]
+pub struct Batch<'a> {
+    pub packets: Vec<Cow<'a, [u8]>>,
+}
+
+#[allow(dead_code)]
+impl<'a> Batch<'a> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// push appends an encoded packet, as written by Protocol::write_packet or LazyPacket::write.
+    #[inline]
+    pub fn push(&mut self, packet: impl Into<Cow<'a, [u8]>>) {
+        self.packets.push(packet.into())
+    }
+
+    /// read reads a batch from an uncompressed game packet, starting at its GAME_PACKET_ID. The packets are
+    /// borrowed from frame. Once compression is negotiated, batches are read with Compression::read_batch.
+    pub fn read(frame: &'a [u8]) -> Result<Self> {
+        Self::decode(game_packet_payload(frame)?)
+    }
+
+    /// write writes the batch as an uncompressed game packet.
+    pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        w.write_u8(GAME_PACKET_ID)?;
+        self.encode(w)
+    }
+
+    /// decode splits payload into its packets without copying them. Only the length prefixes are checked, so
+    /// the batch fails as a whole only if the packets can no longer be told apart. Error offsets count from the
+    /// start of payload.
+    pub fn decode(payload: &'a [u8]) -> Result<Self> {
+        let mut r = SliceReader::new(payload);
+        let mut packets = Vec::new();
+        while !r.remaining().is_empty() {
+            if packets.len() == MAX_BATCH_PACKETS {
+                return Err(Error::new(ErrorKind::LengthLimitExceeded {
+                    len: MAX_BATCH_PACKETS as u64 + 1,
+                    limit: MAX_BATCH_PACKETS as u64,
+                })
+                .in_type("Batch"));
+            }
+            let position = r.position();
+            let packet = Vu32LenBytes::read_borrowed(&mut r)
+                .map_err(|e| e.at_offset(position as u64).in_type("Batch"))?;
+            packets.push(packet.0);
+        }
+        Ok(Self { packets })
+    }
+
+    /// encode writes the length prefixed packets, without the GAME_PACKET_ID.
+    pub fn encode(&self, w: &mut impl Write) -> Result<()> {
+        for packet in &self.packets {
+            LenPrefix::Vu32.write(w, packet.len())?;
+            w.write_all(packet)?;
+        }
+        Ok(())
+    }
+
+    /// encoded_len returns the number of bytes encode writes.
+    pub fn encoded_len(&self) -> usize {
+        self.packets
+            .iter()
+            .map(|packet| LenPrefix::Vu32.encoded_len(packet.len()) + packet.len())
+            .sum()
+    }
+
+    /// lazy_packets reads the header of every packet. An error only affects the packet it is returned for.
+    pub fn lazy_packets<'p>(
+        &'p self,
+        protocol: &'p dyn Protocol,
+    ) -> impl Iterator<Item = Result<LazyPacket<'p>>> + 'p {
+        self.packets
+            .iter()
+            .map(|packet| protocol.read_lazy(&mut SliceReader::new(packet)))
+    }
+
+    /// decode_packets decodes every packet with the pool of protocol. An error only affects the packet it is
+    /// returned for, so the caller may skip it and carry on with the next.
+    pub fn decode_packets<'p>(
+        &'p self,
+        protocol: &'p dyn Protocol,
+    ) -> impl Iterator<Item = Result<(PacketHeader, PacketKind)>> + 'p {
+        self.lazy_packets(protocol).map(move |packet| {
+            let packet = packet?;
+            Ok((packet.header, packet.decode()?))
+        })
+    }
+}
+
+/// game_packet_payload returns what follows the GAME_PACKET_ID that frame starts with.
+pub fn game_packet_payload(frame: &[u8]) -> Result<&[u8]> {
+    match frame.split_first() {
+        Some((&GAME_PACKET_ID, payload)) => Ok(payload),
+        Some((&id, _)) => {
+            Err(Error::new(ErrorKind::InvalidDiscriminant(id as i128)).in_type("Batch"))
+        }
+        None => Err(ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::minecraft::packets::NetworkSettingsPacket;
+    use crate::minecraft::VersionedProtocol;
+
+    use super::*;
+
+    #[test]
+    fn bad_packets_are_skipped() {
+        let protocol: &dyn Protocol = &VersionedProtocol::default();
+        let pk = NetworkSettingsPacket {
+            compression_threshold: 256,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        protocol.write_packet(&mut buf, &pk).unwrap();
+
+        let mut batch = Batch::new();
+        batch.push(buf.as_slice());
+        // a truncated packet only fails on its own, the packets around it still decode.
+        batch.push(&buf[..1]);
+        batch.push(buf.as_slice());
+        let mut frame = Vec::with_capacity(1 + batch.encoded_len());
+        batch.write(&mut frame).unwrap();
+        assert_eq!(frame.len(), 1 + batch.encoded_len());
+
+        let batch = Batch::read(&frame).unwrap();
+        assert_eq!(batch.packets.len(), 3);
+        let packets: Vec<_> = batch.decode_packets(protocol).collect();
+        assert!(packets[1].is_err());
+        for packet in [&packets[0], &packets[2]] {
+            match packet {
+                Ok((_, PacketKind::NetworkSettings(pk))) => {
+                    assert_eq!(pk.compression_threshold, 256)
+                }
+                _ => panic!("expected NetworkSettings"),
+            }
+        }
+    }
+
+    #[test]
+    fn frames() {
+        assert!(Batch::read(&[]).is_err());
+        assert!(Batch::read(&[0x01, 0x00]).is_err());
+        // a length prefix past the end of the frame leaves the packets after it unknown.
+        assert!(Batch::read(&[GAME_PACKET_ID, 0x05, 1, 2]).is_err());
+        assert!(Batch::read(&[GAME_PACKET_ID]).unwrap().packets.is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+use std::io;
+use std::io::{Read, Write};
+
+use bstream::{Error, ErrorKind, Result};
+use byteorder::WriteBytesExt;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use crate::minecraft::batch::{game_packet_payload, Batch, GAME_PACKET_ID};
+use crate::minecraft::packets::{CompressionAlgorithm, NetworkSettingsPacket};
+use crate::minecraft::ProtocolId;
+
+/// DEFAULT_MAX_DECOMPRESSED_LEN is the default limit on the size a batch may decompress to.
+pub const DEFAULT_MAX_DECOMPRESSED_LEN: usize = 16 * 1024 * 1024;
+
+/// Compression compresses batches as negotiated with NetworkSettingsPacket. Until the packet has been sent,
+/// batches are not compressed and are read and written with Batch::read and Batch::write.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    /// threshold is the encoded length from which a batch is compressed. If it is 0, no batch is compressed.
+    /// Protocols before 1.20.60 cannot mark a batch as uncompressed, so they compress every batch.
+    pub threshold: u16,
+    /// prefixed is whether the payload of a game packet starts with the id of its compression algorithm,
+    /// which is the case since 1.20.60.
+    pub prefixed: bool,
+    /// max_decompressed_len is the most bytes a batch may decompress to, so that a small batch cannot
+    /// decompress to an arbitrary amount of memory.
+    pub max_decompressed_len: usize,
+}
+
+#[allow(dead_code)]
+impl Compression {
+    /// new returns the compression negotiated by settings for a connection using protocol.
+    pub fn new(settings: &NetworkSettingsPacket, protocol: ProtocolId) -> Self {
+        Self {
+            algorithm: settings.compression_algorithm.clone(),
+            threshold: settings.compression_threshold,
+            // ProtocolId orders the protocols from the latest, so the ids are compared instead.
+            prefixed: protocol as i32 >= ProtocolId::V1_20_60 as i32,
+            max_decompressed_len: DEFAULT_MAX_DECOMPRESSED_LEN,
+        }
+    }
+
+    /// write_batch writes batch as a game packet, compressed if its encoded length reaches the threshold.
+    pub fn write_batch(&self, batch: &Batch, w: &mut impl Write) -> Result<()> {
+        w.write_u8(GAME_PACKET_ID)?;
+        let len = batch.encoded_len();
+        let compress = !self.prefixed || (self.threshold != 0 && len >= self.threshold as usize);
+        let algorithm = match compress {
+            true => &self.algorithm,
+            false => &CompressionAlgorithm::None,
+        };
+        if self.prefixed {
+            w.write_u8(algorithm_id(algorithm))?;
+        }
+        match algorithm {
+            CompressionAlgorithm::Zlib => {
+                let mut w = DeflateEncoder::new(w, flate2::Compression::default());
+                batch.encode(&mut w)?;
+                w.finish()?;
+            }
+            CompressionAlgorithm::Snappy => {
+                let mut buf = Vec::with_capacity(len);
+                batch.encode(&mut buf)?;
+                let compressed = snap::raw::Encoder::new()
+                    .compress_vec(&buf)
+                    .map_err(io::Error::from)?;
+                w.write_all(&compressed)?;
+            }
+            CompressionAlgorithm::None => batch.encode(w)?,
+        }
+        Ok(())
+    }
+
+    /// read_batch reads a batch from a game packet, starting at its GAME_PACKET_ID. A compressed batch is
+    /// decompressed into buf, which may be reused between batches, and the packets are borrowed from there.
+    pub fn read_batch<'a>(&self, frame: &'a [u8], buf: &'a mut Vec<u8>) -> Result<Batch<'a>> {
+        let mut payload = game_packet_payload(frame)?;
+        let mut algorithm = &self.algorithm;
+        if self.prefixed {
+            let (&id, rest) = payload
+                .split_first()
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof))?;
+            algorithm = match id {
+                0 => &CompressionAlgorithm::Zlib,
+                1 => &CompressionAlgorithm::Snappy,
+                0xff => &CompressionAlgorithm::None,
+                id => {
+                    return Err(Error::new(ErrorKind::InvalidDiscriminant(id as i128))
+                        .in_type("CompressionAlgorithm"))
+                }
+            };
+            payload = rest;
+        }
+        buf.clear();
+        match algorithm {
+            CompressionAlgorithm::Zlib => {
+                // read one byte past the limit to tell a batch at the limit from one over it.
+                DeflateDecoder::new(payload)
+                    .take(self.max_decompressed_len as u64 + 1)
+                    .read_to_end(buf)?;
+                self.check_decompressed_len(buf.len())?;
+            }
+            CompressionAlgorithm::Snappy => {
+                let len = snap::raw::decompress_len(payload).map_err(io::Error::from)?;
+                self.check_decompressed_len(len)?;
+                buf.resize(len, 0);
+                snap::raw::Decoder::new()
+                    .decompress(payload, buf)
+                    .map_err(io::Error::from)?;
+            }
+            CompressionAlgorithm::None => return Batch::decode(payload),
+        }
+        Batch::decode(buf)
+    }
+
+    #[inline]
+    fn check_decompressed_len(&self, len: usize) -> Result<()> {
+        if len > self.max_decompressed_len {
+            return Err(Error::new(ErrorKind::LengthLimitExceeded {
+                len: len as u64,
+                limit: self.max_decompressed_len as u64,
+            })
+            .in_type("Batch"));
+        }
+        Ok(())
+    }
+}
+
+/// algorithm_id returns the byte that marks a batch compressed with algorithm since 1.20.60.
+#[inline]
+fn algorithm_id(algorithm: &CompressionAlgorithm) -> u8 {
+    match algorithm {
+        CompressionAlgorithm::Zlib => 0,
+        CompressionAlgorithm::Snappy => 1,
+        CompressionAlgorithm::None => 0xff,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(algorithm: CompressionAlgorithm, threshold: u16) -> NetworkSettingsPacket {
+        NetworkSettingsPacket {
+            compression_threshold: threshold,
+            compression_algorithm: algorithm,
+            ..Default::default()
+        }
+    }
+
+    fn batch() -> Batch<'static> {
+        let mut batch = Batch::new();
+        batch.push(vec![0x8f; 64]);
+        batch.push(vec![0xc1, 1, 2, 3]);
+        batch
+    }
+
+    #[test]
+    fn compressed_batches_roundtrip() {
+        for (algorithm, id) in [
+            (CompressionAlgorithm::Zlib, 0),
+            (CompressionAlgorithm::Snappy, 1),
+        ] {
+            let compression = Compression::new(&settings(algorithm, 1), ProtocolId::V1_20_60);
+            let mut frame = vec![];
+            compression.write_batch(&batch(), &mut frame).unwrap();
+            assert_eq!(frame[..2], [GAME_PACKET_ID, id]);
+            assert!(frame.len() < 1 + batch().encoded_len());
+            let mut buf = vec![];
+            let read = compression.read_batch(&frame, &mut buf).unwrap();
+            assert_eq!(read.packets, batch().packets);
+        }
+    }
+
+    #[test]
+    fn threshold() {
+        let compression = Compression::new(
+            &settings(CompressionAlgorithm::Zlib, 1024),
+            ProtocolId::V1_20_60,
+        );
+        let mut frame = vec![];
+        compression.write_batch(&batch(), &mut frame).unwrap();
+        assert_eq!(frame[..2], [GAME_PACKET_ID, 0xff]);
+        assert_eq!(frame.len(), 2 + batch().encoded_len());
+
+        // without the prefix, an uncompressed batch cannot be told apart, so every batch is compressed.
+        let compression = Compression::new(
+            &settings(CompressionAlgorithm::Zlib, 1024),
+            ProtocolId::V1_20_50,
+        );
+        let mut frame = vec![];
+        compression.write_batch(&batch(), &mut frame).unwrap();
+        assert!(frame.len() < 1 + batch().encoded_len());
+        let mut buf = vec![];
+        let read = compression.read_batch(&frame, &mut buf).unwrap();
+        assert_eq!(read.packets, batch().packets);
+    }
+
+    #[test]
+    fn max_decompressed_len() {
+        for algorithm in [CompressionAlgorithm::Zlib, CompressionAlgorithm::Snappy] {
+            let compression = Compression::new(&settings(algorithm, 1), ProtocolId::LATEST);
+            let mut frame = vec![];
+            compression.write_batch(&batch(), &mut frame).unwrap();
+            let compression = Compression {
+                max_decompressed_len: 16,
+                ..compression
+            };
+            let mut buf = vec![];
+            assert!(compression.read_batch(&frame, &mut buf).is_err());
+        }
+    }
+}
@@ -1,77 +1,414 @@
+use std::any::type_name;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::{Read, Write};
-use std::io::Result;
+use std::sync::OnceLock;
 
-use bstream::{BinaryStream, ReaderExt, WriterExt};
+use bstream::{
+    check_len, Context, CountingReader, Decode, Encode, Error, ErrorKind, ReaderExt, Result,
+    SliceReader, varint_len, WriterExt,
+};
 
 use crate::minecraft::packets::*;
 
+/// protocols declares ProtocolId from a single list of the supported protocols, so that ProtocolId::ALL and
+/// ProtocolId::version cannot get out of step with the variants.
+macro_rules! protocols {
+    ($($protocol:ident = $id:literal => $version:literal,)*) => {
+        /// ProtocolId lists the protocols that are supported, from the latest to the oldest.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum ProtocolId {
+            $($protocol = $id,)*
+        }
+
+        #[allow(dead_code)]
+        impl ProtocolId {
+            pub const ALL: [ProtocolId; [$($id),*].len()] = [$(ProtocolId::$protocol,)*];
+
+            /// version returns the game version the protocol was introduced with.
+            pub fn version(self) -> &'static str {
+                match self {
+                    $(ProtocolId::$protocol => $version,)*
+                }
+            }
+        }
+    };
+}
+
+protocols! {
+    V1_20_80 = 671 => "v1.20.80",
+    V1_20_70 = 662 => "v1.20.70",
+    V1_20_60 = 649 => "v1.20.60",
+    V1_20_50 = 630 => "v1.20.50",
+    V1_20_40 = 622 => "v1.20.40",
+    V1_20_30 = 618 => "v1.20.30",
+    V1_20_10 = 594 => "v1.20.10",
+    V1_20_0 = 589 => "v1.20.0",
+}
+
+impl Default for ProtocolId {
+    fn default() -> Self {
+        Self::LATEST
+    }
+}
+
 #[allow(dead_code)]
-pub enum ProtocolId {
-    V1_20_80 = 671,
-    V1_20_70 = 662,
-    V1_20_60 = 649,
-    V1_20_50 = 630,
-    V1_20_40 = 622,
-    V1_20_30 = 618,
-    V1_20_10 = 594,
-    V1_20_0 = 589,
+impl ProtocolId {
+    /// LATEST is the first protocol of the list, which is the protocol of the server.
+    pub const LATEST: ProtocolId = Self::ALL[0];
+
+    /// from_id returns the supported protocol with id, if there is one.
+    pub fn from_id(id: i32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|protocol| *protocol as i32 == id)
+    }
+
+    /// negotiate returns the protocol to use with a client that advertised client_protocol in its
+    /// RequestNetworkSettingsPacket. If the protocol is not supported, it returns the PlayStatusPacket that
+    /// the client must be disconnected with: LoginFailedClient if the client is older than the latest
+    /// protocol, and LoginFailedServer if it is newer.
+    pub fn negotiate(client_protocol: i32) -> std::result::Result<Self, PlayStatusPacket> {
+        Self::from_id(client_protocol).ok_or(PlayStatusPacket {
+            status: match client_protocol < Self::LATEST as i32 {
+                true => PlayStatus::LoginFailedClient,
+                false => PlayStatus::LoginFailedServer,
+            },
+        })
+    }
+
+    /// pool returns the decoders of the packets that exist in the protocol by their ids. The pools of every
+    /// protocol are built once and shared from then on, as they never change.
+    pub fn pool(self) -> &'static HashMap<i32, PacketDecoder> {
+        static POOLS: OnceLock<HashMap<ProtocolId, HashMap<i32, PacketDecoder>>> = OnceLock::new();
+        &POOLS.get_or_init(|| {
+            Self::ALL
+                .into_iter()
+                .map(|protocol| (protocol, decoders(protocol)))
+                .collect()
+        })[&self]
+    }
+
+    /// context returns the current Context with its version set to the protocol, under which packets are
+    /// read and written with the fields present in the protocol.
+    #[inline]
+    pub fn context(self) -> Context {
+        Context {
+            version: self as i32,
+            ..Context::current()
+        }
+    }
 }
 
+pub mod batch;
+pub mod compression;
 pub mod packets;
+pub mod translate;
+
+/// Protocol reads and writes the packets of one protocol. It is object safe, so that the protocol of a
+/// connection can be chosen at runtime and stored as a `Box<dyn Protocol>`, and Send and Sync, so that a single
+/// instance can be shared by every connection using it.
+pub trait Protocol: Send + Sync {
+    fn id(&self) -> ProtocolId;
+    #[allow(dead_code)]
+    fn version(&self) -> &'static str;
+    /// pool returns the decoders of the packets that exist in the protocol by their ids.
+    fn pool(&self) -> &HashMap<i32, PacketDecoder>;
+    fn read_packet(&self, r: &mut dyn Read) -> Result<(PacketHeader, PacketKind)>;
+    /// read_lazy reads only the header of a packet and borrows the rest of r as its payload, so that packets
+    /// that are forwarded untouched are never decoded.
+    fn read_lazy<'a>(&self, r: &mut SliceReader<'a>) -> Result<LazyPacket<'a>>;
+    /// write_packet_kind writes a packet that was read with read_packet, including one of an unknown id,
+    /// which is written with the payload it was read with.
+    #[allow(dead_code)]
+    fn write_packet_kind(
+        &self,
+        w: &mut dyn Write,
+        header: PacketHeader,
+        pk: &PacketKind,
+    ) -> Result<()>;
+}
+
+/// Packets of a known type are written through the generic functions below, which cannot be part of the
+/// trait without making it unusable as a trait object.
+impl dyn Protocol + '_ {
+    pub fn write_packet<T: Packet + Encode>(&self, w: &mut impl Write, pk: &T) -> Result<()> {
+        self.write_packet_with(w, PacketHeader::default(), pk)
+    }
+
+    /// write_packet_with writes pk with the sub-clients of header. The id of header is replaced by the id of T
+    /// in the protocol, and an error is returned if T does not exist in the protocol.
+    pub fn write_packet_with<T: Packet + Encode>(
+        &self,
+        w: &mut impl Write,
+        header: PacketHeader,
+        pk: &T,
+    ) -> Result<()> {
+        let id = T::id(self.id()).ok_or_else(|| missing(type_name::<T>(), self.id()))?;
+        self.id().context().scope(|| {
+            PacketHeader { id, ..header }.encode(w)?;
+            pk.encode(w)
+        })
+    }
+}
 
-pub trait Protocol {
-    fn id() -> i32;
-    fn version() -> &'static str;
-    fn pool() -> HashMap<i32, PacketKind>;
-    fn read_packet(pool: &HashMap<i32, PacketKind>, r: &mut impl Read) -> Result<PacketKind>;
-    fn write_packet<T: Packet + BinaryStream>(w: &mut impl Write, pk: &T) -> Result<()>;
+/// VersionedProtocol is the Protocol of a ProtocolId. Packets are read and written under the Context of that
+/// protocol, so only the fields present in the protocol are read and written, and the pool only holds the
+/// packets that exist in the protocol. The default is the latest protocol.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VersionedProtocol {
+    protocol: ProtocolId,
 }
 
-pub struct DefaultProtocol {}
+#[allow(dead_code)]
+impl VersionedProtocol {
+    #[inline]
+    pub fn new(protocol: ProtocolId) -> Self {
+        Self { protocol }
+    }
+}
+
+impl Protocol for VersionedProtocol {
+    fn id(&self) -> ProtocolId {
+        self.protocol
+    }
 
-impl Protocol for DefaultProtocol {
-    fn id() -> i32 {
-        ProtocolId::V1_20_80 as i32
+    fn version(&self) -> &'static str {
+        self.protocol.version()
     }
 
-    fn version() -> &'static str {
-        "v1.20.80"
+    fn pool(&self) -> &HashMap<i32, PacketDecoder> {
+        self.protocol.pool()
     }
 
-    fn pool() -> HashMap<i32, PacketKind> {
-        HashMap::from([
-            (
-                RequestNetworkSettingsPacket::id(),
-                RequestNetworkSettingsPacket::kind(),
-            ),
-            (NetworkSettingsPacket::id(), NetworkSettingsPacket::kind()),
-            (LoginPacket::id(), LoginPacket::kind()),
-        ])
+    fn read_packet(&self, r: &mut dyn Read) -> Result<(PacketHeader, PacketKind)> {
+        self.protocol.context().scope(|| {
+            let mut r = CountingReader::new(r);
+            let header = PacketHeader::decode(&mut r)?;
+            let pk = match self.pool().get(&header.id) {
+                Some(decoder) => decoder(&mut r).map_err(|e| e.at_offset(r.position()))?,
+                None => {
+                    // without a decoder the end of the packet is unknown, so the packet is the rest of r, as
+                    // it is when r holds a single packet of a batch.
+                    let mut payload = Vec::new();
+                    let limit = Context::current().max_len as u64;
+                    (&mut r).take(limit + 1).read_to_end(&mut payload)?;
+                    check_len(payload.len() as u64).map_err(|e| e.at_offset(r.position()))?;
+                    PacketKind::Unknown {
+                        id: header.id,
+                        payload,
+                    }
+                }
+            };
+            Ok((header, pk))
+        })
     }
 
-    fn read_packet(pool: &HashMap<i32, PacketKind>, r: &mut impl Read) -> Result<PacketKind> {
-        let header = r.read_vi32()?;
-        let pid = header & 0x3ff;
-        let kind = pool.get(&pid).unwrap();
-        decode_kind(r, kind)
+    fn read_lazy<'a>(&self, r: &mut SliceReader<'a>) -> Result<LazyPacket<'a>> {
+        let header = PacketHeader::decode(r)?;
+        let payload = r.read_slice(r.remaining().len())?;
+        Ok(LazyPacket {
+            header,
+            protocol: self.protocol,
+            payload: Cow::Borrowed(payload),
+        })
     }
 
-    fn write_packet<T: Packet + BinaryStream>(w: &mut impl Write, pk: &T) -> Result<()> {
-        w.write_vi32(T::id())?;
-        pk.write(w)
+    fn write_packet_kind(
+        &self,
+        mut w: &mut dyn Write,
+        header: PacketHeader,
+        pk: &PacketKind,
+    ) -> Result<()> {
+        let id = pk
+            .id(self.protocol)
+            .ok_or_else(|| missing(pk.name(), self.protocol))?;
+        self.protocol.context().scope(|| {
+            PacketHeader { id, ..header }.encode(&mut w)?;
+            pk.encode(&mut w)
+        })
+    }
+}
+
+/// missing returns the error of writing the packet named name with a protocol it does not exist in.
+fn missing(name: &str, protocol: ProtocolId) -> Error {
+    Error::new(ErrorKind::Message(format!(
+        "{} does not exist in protocol {:?}",
+        name, protocol
+    )))
+}
+
+/// PacketHeader precedes every packet as a varuint32. It holds the id of the packet in bits 0-9, and the
+/// sub-clients that sent and are targeted by the packet in bits 10-11 and 12-13. Sub-clients are the extra
+/// players of a split-screen session, with 0 being the main player.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub id: i32,
+    pub sender_sub_client: u8,
+    pub target_sub_client: u8,
+}
+
+impl PacketHeader {
+    #[inline]
+    fn bits(&self) -> u32 {
+        (self.id as u32 & 0x3ff)
+            | (self.sender_sub_client as u32 & 0x3) << 10
+            | (self.target_sub_client as u32 & 0x3) << 12
+    }
+}
+
+impl Decode for PacketHeader {
+    fn decode(out: &mut impl Read) -> Result<Self> {
+        let header = out.read_vu32()?;
+        Ok(Self {
+            id: (header & 0x3ff) as i32,
+            sender_sub_client: (header >> 10 & 0x3) as u8,
+            target_sub_client: (header >> 12 & 0x3) as u8,
+        })
+    }
+}
+
+impl Encode for PacketHeader {
+    fn encode(&self, out: &mut impl Write) -> Result<()> {
+        Ok(out.write_vu32(self.bits())?)
+    }
+
+    fn encoded_len(&self) -> usize {
+        varint_len(self.bits() as u64)
+    }
+}
+
+/// LazyPacket is a packet of which only the header has been read. The payload is kept as it was received, so
+/// that it can be written again as is, or decoded once it turns out to be needed.
+#[derive(Debug, Clone)]
+pub struct LazyPacket<'a> {
+    pub header: PacketHeader,
+    /// protocol is the protocol the packet was read with, under which its payload is decoded.
+    pub protocol: ProtocolId,
+    pub payload: Cow<'a, [u8]>,
+}
+
+impl LazyPacket<'_> {
+    #[inline]
+    pub fn id(&self) -> i32 {
+        self.header.id
+    }
+
+    /// decode decodes the payload as the packet registered for its id in the pool of its protocol, or returns
+    /// it as PacketKind::Unknown if there is none.
+    pub fn decode(&self) -> Result<PacketKind> {
+        let Some(decoder) = self.protocol.pool().get(&self.id()) else {
+            return Ok(PacketKind::Unknown {
+                id: self.id(),
+                payload: self.payload.to_vec(),
+            });
+        };
+        let mut r = SliceReader::new(&self.payload);
+        self.protocol.context().scope(|| {
+            decoder(&mut r).map_err(|e| {
+                // offsets count from the start of the packet, like those reported by read_packet.
+                e.at_offset((self.header.encoded_len() + r.position()) as u64)
+            })
+        })
+    }
+
+    /// write writes the packet with the header and payload it was read with.
+    #[allow(dead_code)]
+    pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.header.encode(w)?;
+        Ok(w.write_all(&self.payload)?)
     }
 }
 
 pub trait Packet {
     fn compressible() -> bool;
-    fn id() -> i32;
-    fn kind() -> PacketKind;
+    /// id returns the id of the packet in protocol, or None if the packet does not exist in protocol.
+    fn id(protocol: ProtocolId) -> Option<i32>;
+    fn decoder() -> PacketDecoder;
 }
 
-#[inline]
-fn decode<T: Packet + Clone + BinaryStream>(pk: &T, r: &mut impl Read) -> Result<T> {
-    let mut cpk = pk.clone();
-    cpk.read(r)?;
-    Ok(cpk)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(ProtocolId::negotiate(622).ok(), Some(ProtocolId::V1_20_40));
+        for client_protocol in [0, 600, 670] {
+            let pk = ProtocolId::negotiate(client_protocol).unwrap_err();
+            assert!(matches!(pk.status, PlayStatus::LoginFailedClient));
+        }
+        for client_protocol in [672, 700] {
+            let pk = ProtocolId::negotiate(client_protocol).unwrap_err();
+            assert!(matches!(pk.status, PlayStatus::LoginFailedServer));
+        }
+    }
+
+    #[test]
+    fn protocols() {
+        assert_eq!(ProtocolId::LATEST, ProtocolId::V1_20_80);
+        assert_eq!(ProtocolId::default(), ProtocolId::LATEST);
+        for protocol in ProtocolId::ALL {
+            assert_eq!(ProtocolId::from_id(protocol as i32), Some(protocol));
+        }
+        assert!(ProtocolId::ALL
+            .windows(2)
+            .all(|w| w[0] as i32 > w[1] as i32));
+        assert_eq!(ProtocolId::V1_20_60.version(), "v1.20.60");
+    }
+
+    #[test]
+    fn unknown_packets_are_forwarded() {
+        let protocol: &dyn Protocol = &VersionedProtocol::default();
+        let unknown = [0xff, 0x07, 1, 2, 3];
+        let (header, kind) = protocol.read_packet(&mut &unknown[..]).unwrap();
+        assert_eq!(header.id, 0x3ff);
+        assert!(
+            matches!(&kind, PacketKind::Unknown { id: 0x3ff, payload } if payload == &[1, 2, 3])
+        );
+        let mut forwarded = vec![];
+        protocol
+            .write_packet_kind(&mut forwarded, header, &kind)
+            .unwrap();
+        assert_eq!(unknown[..], forwarded[..]);
+    }
+
+    #[test]
+    fn sub_clients() {
+        let protocol: &dyn Protocol = &VersionedProtocol::default();
+        let header = PacketHeader {
+            id: 0,
+            sender_sub_client: 2,
+            target_sub_client: 3,
+        };
+        let mut buf = vec![];
+        protocol
+            .write_packet_with(&mut buf, header, &PlayStatusPacket::default())
+            .unwrap();
+        assert_eq!(buf[..2], [0x82, 0x70]);
+        let (read, _) = protocol.read_packet(&mut buf.as_slice()).unwrap();
+        assert_eq!(read, PacketHeader { id: 0x02, ..header });
+        assert_eq!(read.encoded_len(), 2);
+    }
+
+    #[test]
+    fn lazy_packets() {
+        let protocol: &dyn Protocol = &VersionedProtocol::default();
+        let pk = NetworkSettingsPacket {
+            compression_threshold: 256,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        protocol.write_packet(&mut buf, &pk).unwrap();
+        let lazy = protocol.read_lazy(&mut SliceReader::new(&buf)).unwrap();
+        assert_eq!(lazy.id(), 0x8f);
+        assert!(matches!(lazy.payload, Cow::Borrowed(_)));
+        let mut forwarded = vec![];
+        lazy.write(&mut forwarded).unwrap();
+        assert_eq!(buf, forwarded);
+        match lazy.decode().unwrap() {
+            PacketKind::NetworkSettings(pk) => assert_eq!(pk.compression_threshold, 256),
+            _ => panic!("expected NetworkSettings"),
+        }
+    }
 }
@@ -1,50 +1,199 @@
-use std::io::Read;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::ops::Range;
 
-use bstream::{EnumBinaryStream, Vu32LenByteSlice};
+use bstream::{Decode, Encode, VarI32, Vu32LenByteSlice};
 use bstream_macro::{b_enum, BStream};
+use vmath::vec3::Vec3;
 
 use crate::minecraft::*;
-use crate::minecraft::packets::PacketKind::*;
-
-macro_rules! register_pk {
-    ($name:tt,$id:tt,$c:tt,$kind:tt) => {
-        impl Packet for $name {
-            #[inline]
-            fn compressible() -> bool {
-                return $c;
+
+/// packets registers every packet: it implements Packet for each of them, and generates the PacketKind
+/// variant, the decoder and the registry entries of each packet. A packet is registered as
+/// `#[packet(id = 0x02, compressible, since = 662, until = 671)] Variant(PacketType),`, where the options
+/// after the id may be left out or given in any order. since is the first protocol with the packet, and until
+/// the first protocol without it. A packet of which the id changed has one `#[packet(...)]` per id, with
+/// versions that do not overlap. Ids must be unique, which is checked at compile time.
+macro_rules! packets {
+    (@check) => {};
+    (@check compressible, $($rest:tt)*) => {
+        packets!(@check $($rest)*);
+    };
+    (@check since = $since:literal, $($rest:tt)*) => {
+        packets!(@check $($rest)*);
+    };
+    (@check until = $until:literal, $($rest:tt)*) => {
+        packets!(@check $($rest)*);
+    };
+    (@check $option:ident $(= $value:literal)?, $($rest:tt)*) => {
+        compile_error!(concat!("unknown packet option ", stringify!($option)));
+    };
+    (@compressible) => {
+        false
+    };
+    (@compressible compressible, $($rest:tt)*) => {
+        true
+    };
+    (@compressible $option:ident $(= $value:literal)?, $($rest:tt)*) => {
+        packets!(@compressible $($rest)*)
+    };
+    (@since) => {
+        i32::MIN
+    };
+    (@since since = $since:literal, $($rest:tt)*) => {
+        $since
+    };
+    (@since $option:ident $(= $value:literal)?, $($rest:tt)*) => {
+        packets!(@since $($rest)*)
+    };
+    (@until) => {
+        i32::MAX
+    };
+    (@until until = $until:literal, $($rest:tt)*) => {
+        $until
+    };
+    (@until $option:ident $(= $value:literal)?, $($rest:tt)*) => {
+        packets!(@until $($rest)*)
+    };
+    ($(
+        $(#[packet(id = $id:literal $(, $option:ident $(= $value:literal)?)*)])+
+        $kind:ident($name:ident),
+    )*) => {
+        $(
+            $(packets!(@check $($option $(= $value)?,)*);)+
+
+            impl Packet for $name {
+                #[inline]
+                fn compressible() -> bool {
+                    packets!(@compressible $($($option $(= $value)?,)*)+)
+                }
+                fn id(protocol: ProtocolId) -> Option<i32> {
+                    [$((
+                        $id,
+                        packets!(@since $($option $(= $value)?,)*)..packets!(@until $($option $(= $value)?,)*),
+                    ),)+]
+                    .into_iter()
+                    .find(|(_, versions)| versions.contains(&(protocol as i32)))
+                    .map(|(id, _)| id)
+                }
+                #[inline]
+                fn decoder() -> PacketDecoder {
+                    |mut r| Ok(PacketKind::$kind(<$name as Decode>::decode(&mut r)?))
+                }
+            }
+        )*
+
+        // a duplicate id makes its arm unreachable.
+        #[deny(unreachable_patterns)]
+        const _: () = match 0 {
+            $($($id => (),)+)*
+            _ => (),
+        };
+
+        // PacketHeader::bits keeps the low 10 bits of an id, so a larger id would be written as another packet.
+        $($(const _: () = assert!(
+            $id >= 0 && $id <= 0x3ff,
+            concat!("packet id ", stringify!($id), " does not fit in the 10 bits of a header"),
+        );)+)*
+
+        /// REGISTRY lists every `#[packet(...)]` of every packet, which is the id of each packet by protocol.
+        pub const REGISTRY: &[Registration] = &[$($(
+            Registration {
+                name: stringify!($name),
+                id: $id,
+                versions: packets!(@since $($option $(= $value)?,)*)..packets!(@until $($option $(= $value)?,)*),
+            },
+        )+)*];
+
+        pub enum PacketKind {
+            $($kind($name),)*
+            /// Unknown is a packet of an id without a decoder. Its payload is kept as it was read, so that it
+            /// can be forwarded without being understood.
+            #[allow(dead_code)]
+            Unknown { id: i32, payload: Vec<u8> },
+        }
+
+        impl PacketKind {
+            /// id returns the id of the packet in protocol, or None if the packet does not exist in protocol.
+            #[allow(dead_code)]
+            pub fn id(&self, protocol: ProtocolId) -> Option<i32> {
+                match self {
+                    $(PacketKind::$kind(_) => <$name as Packet>::id(protocol),)*
+                    PacketKind::Unknown { id, .. } => Some(*id),
+                }
+            }
+
+            /// name returns the name of the packet, which is "Unknown" for a packet of an unknown id.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(PacketKind::$kind(_) => stringify!($name),)*
+                    PacketKind::Unknown { .. } => "Unknown",
+                }
             }
-            #[inline]
-            fn id() -> i32 {
-                return $id;
+        }
+
+        /// PacketKind is encoded as the payload of its packet, without a header.
+        impl Encode for PacketKind {
+            fn encode(&self, out: &mut impl Write) -> Result<()> {
+                match self {
+                    $(PacketKind::$kind(pk) => pk.encode(out),)*
+                    PacketKind::Unknown { payload, .. } => Ok(out.write_all(payload)?),
+                }
             }
-            #[inline]
-            fn kind() -> PacketKind {
-                return $kind(Default::default());
+
+            fn encoded_len(&self) -> usize {
+                match self {
+                    $(PacketKind::$kind(pk) => pk.encoded_len(),)*
+                    PacketKind::Unknown { payload, .. } => payload.len(),
+                }
             }
         }
+
+        /// decoders returns the decoder of every packet that exists in protocol by its id.
+        pub fn decoders(protocol: ProtocolId) -> HashMap<i32, PacketDecoder> {
+            [$((<$name as Packet>::id(protocol), <$name as Packet>::decoder()),)*]
+                .into_iter()
+                .filter_map(|(id, decoder)| Some((id?, decoder)))
+                .collect()
+        }
     };
 }
 
-pub enum PacketKind {
-    RequestNetworkSettings(RequestNetworkSettingsPacket),
-    NetworkSettings(NetworkSettingsPacket),
+packets! {
+    #[packet(id = 0x01, compressible)]
     Login(LoginPacket),
+    #[packet(id = 0x02, compressible)]
     PlayStatus(PlayStatusPacket),
+    #[packet(id = 0x05, compressible)]
+    Disconnect(DisconnectPacket),
+    #[packet(id = 0x1b, compressible)]
     ActorEvent(ActorEventPacket),
+    #[packet(id = 0x23, compressible)]
     ActorPickRequest(ActorPickRequestPacket),
+    #[packet(id = 0x28, compressible)]
+    SetActorMotion(SetActorMotionPacket),
+    #[packet(id = 0x7d, compressible)]
+    LecternUpdate(LecternUpdatePacket),
+    #[packet(id = 0x8f)]
+    NetworkSettings(NetworkSettingsPacket),
+    #[packet(id = 0xc1)]
+    RequestNetworkSettings(RequestNetworkSettingsPacket),
+    #[packet(id = 0x134, compressible, since = 649)]
+    SetHud(SetHudPacket),
 }
 
-pub fn decode_kind(r: &mut impl Read, kind: &PacketKind) -> Result<PacketKind> {
-    Ok(match kind {
-        RequestNetworkSettings(pk) => RequestNetworkSettings(decode(pk, r)?),
-        NetworkSettings(pk) => NetworkSettings(decode(pk, r)?),
-        Login(pk) => Login(decode(pk, r)?),
-        PlayStatus(pk) => PlayStatus(decode(pk, r)?),
-        ActorEvent(pk) => ActorEvent(decode(pk, r)?),
-        ActorPickRequest(pk) => ActorPickRequest(decode(pk, r)?),
-    })
+/// Registration is a `#[packet(...)]` of a packet: the packet named name has id in the protocols within
+/// versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    pub name: &'static str,
+    pub id: i32,
+    pub versions: Range<i32>,
 }
 
+/// PacketDecoder decodes the payload of a packet into the PacketKind variant of that packet.
+pub type PacketDecoder = fn(&mut dyn Read) -> Result<PacketKind>;
+
 /// RequestNetworkSettingsPacket is sent by the client to request network settings, such as compression, from the server.
 #[derive(Debug, Clone, Default, BStream)]
 pub struct RequestNetworkSettingsPacket {
@@ -54,13 +203,6 @@ pub struct RequestNetworkSettingsPacket {
     pub client_protocol: i32,
 }
 
-register_pk!(
-    RequestNetworkSettingsPacket,
-    0xc1,
-    false,
-    RequestNetworkSettings
-);
-
 #[derive(Clone, Debug, Default)]
 #[b_enum(u16)]
 pub enum CompressionAlgorithm {
@@ -93,8 +235,6 @@ pub struct NetworkSettingsPacket {
     pub client_throttle_scalar: f32,
 }
 
-register_pk!(NetworkSettingsPacket, 0x8f, false, NetworkSettings);
-
 /// LoginPacket is sent when the client initially tries to join the server. It is the first packet sent and contains
 /// information specific to the player.
 #[derive(Debug, Clone, Default, BStream)]
@@ -109,8 +249,6 @@ pub struct LoginPacket {
     pub connection_request: Vu32LenByteSlice,
 }
 
-register_pk!(LoginPacket, 0x01, true, Login);
-
 #[derive(Debug, Clone, Default)]
 #[b_enum(i32, BigEndian)]
 pub enum PlayStatus {
@@ -125,6 +263,8 @@ pub enum PlayStatus {
     LoginFailedServerFull = 7,
     LoginFailedEditorVanilla = 8,
     LoginFailedVanillaEditor = 9,
+    #[unknown]
+    Unknown(i32),
 }
 
 /// PlayStatus is sent by the server to update a player on the play status. This includes failed statuses due
@@ -132,13 +272,11 @@ pub enum PlayStatus {
 #[derive(Debug, Clone, Default, BStream)]
 pub struct PlayStatusPacket {
     /// status is the status of the packet. It is one of the constants found above.
-    status: PlayStatus,
+    pub status: PlayStatus,
 }
 
-register_pk!(PlayStatusPacket, 0x02, true, PlayStatus);
-
 #[derive(Debug, Clone, Default)]
-#[b_enum(u64, Varint)]
+#[b_enum(u8)]
 pub enum ActorEvent {
     #[default]
     Jump = 1,
@@ -202,6 +340,8 @@ pub enum ActorEvent {
     GrowUp = 76,
     VibrationDetected = 77,
     DrinkMilk = 78,
+    #[unknown]
+    Unknown(u8),
 }
 
 /// ActorEventPacket is sent by the server when a particular event happens that has to do with an entity. Some of
@@ -211,17 +351,16 @@ pub enum ActorEvent {
 pub struct ActorEventPacket {
     /// entity_runtime_id is the runtime ID of the entity. The runtime ID is unique for each world session, and
     /// entities are generally identified in packets using this runtime ID.
-    pub entity_runtime_id: ActorEvent,
+    #[Varint]
+    pub entity_runtime_id: u64,
     /// event_type is the ID of the event to be called. It is one of the constants that can be found above.
-    pub event_type: u8,
+    pub event_type: ActorEvent,
     /// event_data is optional data associated with a particular event. The data has a different function for
     /// different event,s, however most events don't use this field at all.
     #[Varint]
-    pub event_data: u32,
+    pub event_data: i32,
 }
 
-register_pk!(ActorEventPacket, 0x1b, true, ActorEvent);
-
 /// ActorPickRequest is sent by the client when it tries to pick an entity, so that it gets a spawn egg which
 /// can spawn that entity.
 #[derive(Debug, Clone, Default, BStream)]
@@ -236,4 +375,161 @@ pub struct ActorPickRequestPacket {
     pub with_data: bool,
 }
 
-register_pk!(ActorPickRequestPacket, 0x23, true, ActorPickRequest);
+/// DisconnectPacket may be sent by the server to disconnect the client using an optional message to send as
+/// the disconnect screen.
+#[derive(Debug, Clone, Default, BStream)]
+pub struct DisconnectPacket {
+    /// reason is the reason for the disconnection, which the client reports in its telemetry. It was added in
+    /// v1.20.40.
+    #[Varint]
+    #[since(622)]
+    pub reason: i32,
+    /// hide_disconnection_screen specifies if the disconnection screen should be hidden when the client is
+    /// disconnected, meaning it will be sent directly to the main menu.
+    pub hide_disconnection_screen: bool,
+    /// message is an optional message to show when disconnected. It is only sent if the disconnection screen
+    /// is shown.
+    #[bstream(if = "!self.hide_disconnection_screen")]
+    pub message: String,
+}
+
+/// SetActorMotionPacket is sent by the server to change the client-side velocity of an entity. It is usually
+/// used in combination with server-side movement calculation.
+#[derive(Debug, Clone, Default, BStream)]
+pub struct SetActorMotionPacket {
+    /// entity_runtime_id is the runtime ID of the entity. The runtime ID is unique for each world session, and
+    /// entities are generally identified in packets using this runtime ID.
+    #[Varint]
+    pub entity_runtime_id: u64,
+    /// velocity is the new velocity the entity gets. This velocity will initiate the client-side movement of
+    /// the entity.
+    pub velocity: Vec3<f32>,
+    /// tick is the server tick at which the packet was sent. It was added in v1.20.80.
+    #[Varint]
+    #[since(671)]
+    pub tick: u64,
+}
+
+/// BlockPos is the position of a block, of which the y coordinate is never negative.
+#[derive(Debug, Clone, Default, BStream)]
+pub struct BlockPos {
+    #[Varint]
+    pub x: i32,
+    #[Varint]
+    pub y: u32,
+    #[Varint]
+    pub z: i32,
+}
+
+/// LecternUpdatePacket is sent by the client to update the server on which page was opened in a book on a
+/// lectern, or if the book should be removed from it.
+#[derive(Debug, Clone, Default, BStream)]
+pub struct LecternUpdatePacket {
+    /// page is the page number in the book that was opened by the player on the lectern.
+    pub page: u8,
+    /// page_count is the number of pages that the book opened in the lectern has.
+    pub page_count: u8,
+    /// position is the position of the lectern that was updated.
+    pub position: BlockPos,
+    /// drop_book specifies if the book currently set on display in the lectern should be dropped by the
+    /// server. It was removed in v1.20.70.
+    #[until(662)]
+    pub drop_book: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+#[b_enum(i32, Varint)]
+pub enum HudVisibility {
+    #[default]
+    Hide = 0,
+    Reset = 1,
+    #[unknown]
+    Unknown(i32),
+}
+
+/// SetHudPacket is sent by the server to set the visibility of individual HUD elements on the client. It was
+/// added in v1.20.60.
+#[derive(Debug, Clone, Default, BStream)]
+pub struct SetHudPacket {
+    /// elements is a list of the HUD elements that are being modified.
+    pub elements: Vec<VarI32>,
+    /// visibility is the new visibility of the specified elements.
+    pub visibility: HudVisibility,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_in<T: Encode>(protocol: ProtocolId, pk: &T) -> Vec<u8> {
+        protocol.context().scope(|| {
+            let mut buf = vec![];
+            pk.encode(&mut buf).unwrap();
+            assert_eq!(buf.len(), pk.encoded_len());
+            buf
+        })
+    }
+
+    #[test]
+    fn packets_exist_from_since_until_until() {
+        assert_eq!(SetHudPacket::id(ProtocolId::V1_20_50), None);
+        assert_eq!(SetHudPacket::id(ProtocolId::V1_20_60), Some(0x134));
+        assert!(!ProtocolId::V1_20_50.pool().contains_key(&0x134));
+        assert!(ProtocolId::V1_20_60.pool().contains_key(&0x134));
+        assert!(ProtocolId::LATEST.pool().contains_key(&0x134));
+        for protocol in ProtocolId::ALL {
+            assert_eq!(DisconnectPacket::id(protocol), Some(0x05));
+            assert!(protocol.pool().contains_key(&0x05));
+        }
+        let registration = REGISTRY.iter().find(|r| r.name == "SetHudPacket").unwrap();
+        assert_eq!(registration.versions, 649..i32::MAX);
+    }
+
+    #[test]
+    fn names() {
+        let pk = PacketKind::SetHud(SetHudPacket::default());
+        assert_eq!(pk.name(), "SetHudPacket");
+        let pk = PacketKind::Unknown {
+            id: 0x3ff,
+            payload: vec![],
+        };
+        assert_eq!(pk.name(), "Unknown");
+        let latest = ProtocolId::LATEST as i32;
+        for registration in REGISTRY.iter().filter(|r| r.versions.contains(&latest)) {
+            let decoder = ProtocolId::LATEST.pool()[&registration.id];
+            let pk = decoder(&mut &[0u8; 64][..]).unwrap();
+            assert_eq!(pk.name(), registration.name);
+        }
+    }
+
+    #[test]
+    fn fields_follow_the_protocol() {
+        let pk = DisconnectPacket {
+            reason: 1,
+            hide_disconnection_screen: true,
+            message: String::new(),
+        };
+        assert_eq!(encode_in(ProtocolId::V1_20_30, &pk), [1]);
+        assert_eq!(encode_in(ProtocolId::V1_20_40, &pk), [2, 1]);
+
+        let pk = LecternUpdatePacket {
+            drop_book: true,
+            ..Default::default()
+        };
+        assert_eq!(encode_in(ProtocolId::V1_20_60, &pk).len(), 6);
+        assert_eq!(encode_in(ProtocolId::V1_20_70, &pk).len(), 5);
+
+        let pk = SetActorMotionPacket {
+            tick: 1,
+            ..Default::default()
+        };
+        assert_eq!(encode_in(ProtocolId::V1_20_70, &pk).len(), 13);
+        let buf = encode_in(ProtocolId::V1_20_80, &pk);
+        assert_eq!(buf.len(), 14);
+        let decoded = ProtocolId::V1_20_80
+            .context()
+            .scope(|| SetActorMotionPacket::decode(&mut buf.as_slice()))
+            .unwrap();
+        assert_eq!(decoded.tick, 1);
+    }
+}
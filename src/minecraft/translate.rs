@@ -0,0 +1,219 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use bstream::{Encode, Error, ErrorKind, Result};
+
+use crate::minecraft::packets::{Registration, REGISTRY};
+use crate::minecraft::{LazyPacket, PacketHeader, ProtocolId};
+
+/// Translator translates packets read with one protocol into packets of another, so that a client of one
+/// version can be proxied to a server of another. A translator only works in one direction: a connection
+/// needs one from the client to the server and one from the server to the client.
+///
+/// A packet is decoded with the fields present in from and encoded with those present in to, so fields added
+/// since from are written with their default value, and fields removed since from are dropped. Packets that do
+/// not exist in to are dropped entirely.
+#[allow(dead_code)]
+pub struct Translator {
+    pub from: ProtocolId,
+    pub to: ProtocolId,
+    /// ids maps the id of a packet in from to its id in to, for packets whose id changed between the two.
+    pub ids: HashMap<i32, i32>,
+}
+
+#[allow(dead_code)]
+impl Translator {
+    pub fn new(from: ProtocolId, to: ProtocolId) -> Self {
+        Self {
+            from,
+            to,
+            ids: remap(REGISTRY, from, to),
+        }
+    }
+
+    /// translate translates packet, which must have been read with the protocol from. It returns None if the
+    /// packet does not exist in to and should be dropped. Packets that are the same in both protocols, and
+    /// packets of ids without a decoder, keep the payload they were read with.
+    pub fn translate<'a>(&self, packet: LazyPacket<'a>) -> Result<Option<LazyPacket<'a>>> {
+        if packet.protocol != self.from {
+            return Err(Error::new(ErrorKind::Message(format!(
+                "packet read with protocol {:?} cannot be translated from protocol {:?}",
+                packet.protocol, self.from
+            ))));
+        }
+        let header = PacketHeader {
+            id: self.ids.get(&packet.id()).copied().unwrap_or(packet.id()),
+            ..packet.header
+        };
+        if self.from == self.to || !self.from.pool().contains_key(&packet.id()) {
+            return Ok(Some(LazyPacket {
+                header,
+                protocol: self.to,
+                payload: packet.payload,
+            }));
+        }
+        if !self.to.pool().contains_key(&header.id) {
+            return Ok(None);
+        }
+        let pk = packet.decode()?;
+        let payload = self.to.context().scope(|| -> Result<Vec<u8>> {
+            let mut payload = Vec::with_capacity(pk.encoded_len());
+            pk.encode(&mut payload)?;
+            Ok(payload)
+        })?;
+        Ok(Some(LazyPacket {
+            header,
+            protocol: self.to,
+            payload: Cow::Owned(payload),
+        }))
+    }
+}
+
+/// remap maps the id of every packet of registry in from to its id in to, for the packets that exist in both
+/// protocols with a different id.
+fn remap(registry: &[Registration], from: ProtocolId, to: ProtocolId) -> HashMap<i32, i32> {
+    let id = |name: &str, protocol: ProtocolId| {
+        registry
+            .iter()
+            .find(|r| r.name == name && r.versions.contains(&(protocol as i32)))
+            .map(|r| r.id)
+    };
+    registry
+        .iter()
+        .filter_map(|r| Some((id(r.name, from)?, id(r.name, to)?)))
+        .filter(|(from, to)| from != to)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bstream::SliceReader;
+
+    use crate::minecraft::batch::Batch;
+    use crate::minecraft::packets::{
+        DisconnectPacket, LecternUpdatePacket, RequestNetworkSettingsPacket, SetHudPacket,
+    };
+    use crate::minecraft::{Packet, Protocol, ProtocolId, VersionedProtocol};
+
+    use super::*;
+
+    #[test]
+    fn translate_batch() {
+        let client: &dyn Protocol = &VersionedProtocol::new(ProtocolId::V1_20_40);
+        let request = RequestNetworkSettingsPacket {
+            client_protocol: ProtocolId::V1_20_40 as i32,
+        };
+        let mut buf = vec![];
+        client.write_packet(&mut buf, &request).unwrap();
+        let mut batch = Batch::new();
+        batch.push(buf.as_slice());
+
+        let serverbound = Translator::new(ProtocolId::V1_20_40, ProtocolId::LATEST);
+        let mut translated = Batch::new();
+        for packet in batch.lazy_packets(client) {
+            if let Some(packet) = serverbound.translate(packet.unwrap()).unwrap() {
+                assert_eq!(packet.protocol, ProtocolId::LATEST);
+                let mut buf = vec![];
+                packet.write(&mut buf).unwrap();
+                translated.push(buf);
+            }
+        }
+        assert_eq!(translated.packets, batch.packets);
+        let packet = VersionedProtocol::default()
+            .read_lazy(&mut SliceReader::new(&translated.packets[0]))
+            .unwrap();
+        assert_eq!(packet.id(), 0xc1);
+    }
+
+    fn lazy<T: Packet + Encode>(protocol: ProtocolId, pk: &T) -> LazyPacket<'static> {
+        let protocol: &dyn Protocol = &VersionedProtocol::new(protocol);
+        let mut buf = vec![];
+        protocol.write_packet(&mut buf, pk).unwrap();
+        let packet = protocol.read_lazy(&mut SliceReader::new(&buf)).unwrap();
+        LazyPacket {
+            payload: Cow::Owned(packet.payload.into_owned()),
+            ..packet
+        }
+    }
+
+    #[test]
+    fn added_fields_are_filled() {
+        let pk = DisconnectPacket {
+            hide_disconnection_screen: true,
+            ..Default::default()
+        };
+        let packet = lazy(ProtocolId::V1_20_30, &pk);
+        assert_eq!(packet.payload[..], [1]);
+        let translated = Translator::new(ProtocolId::V1_20_30, ProtocolId::V1_20_40)
+            .translate(packet)
+            .unwrap()
+            .unwrap();
+        assert_eq!(translated.protocol, ProtocolId::V1_20_40);
+        assert_eq!(translated.payload[..], [0, 1]);
+    }
+
+    #[test]
+    fn removed_fields_are_dropped() {
+        let pk = LecternUpdatePacket {
+            page: 1,
+            drop_book: true,
+            ..Default::default()
+        };
+        let packet = lazy(ProtocolId::V1_20_60, &pk);
+        assert_eq!(packet.payload.len(), 6);
+        let translated = Translator::new(ProtocolId::V1_20_60, ProtocolId::V1_20_70)
+            .translate(packet)
+            .unwrap()
+            .unwrap();
+        assert_eq!(translated.payload[..], [1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn missing_packets_are_dropped() {
+        let packet = lazy(ProtocolId::LATEST, &SetHudPacket::default());
+        let clientbound = Translator::new(ProtocolId::LATEST, ProtocolId::V1_20_50);
+        assert!(clientbound.translate(packet).unwrap().is_none());
+    }
+
+    #[test]
+    fn packets_of_another_protocol_are_rejected() {
+        let packet = lazy(ProtocolId::V1_20_30, &DisconnectPacket::default());
+        let serverbound = Translator::new(ProtocolId::V1_20_40, ProtocolId::LATEST);
+        assert!(serverbound.translate(packet).is_err());
+    }
+
+    #[test]
+    fn ids_are_remapped() {
+        let registry = [
+            Registration {
+                name: "Moved",
+                id: 1,
+                versions: i32::MIN..630,
+            },
+            Registration {
+                name: "Moved",
+                id: 2,
+                versions: 630..i32::MAX,
+            },
+            Registration {
+                name: "Removed",
+                id: 3,
+                versions: i32::MIN..630,
+            },
+            Registration {
+                name: "Kept",
+                id: 4,
+                versions: i32::MIN..i32::MAX,
+            },
+        ];
+        let ids = remap(&registry, ProtocolId::V1_20_40, ProtocolId::V1_20_50);
+        assert_eq!(ids, HashMap::from([(1, 2)]));
+        let ids = remap(&registry, ProtocolId::V1_20_50, ProtocolId::V1_20_40);
+        assert_eq!(ids, HashMap::from([(2, 1)]));
+        assert!(remap(&registry, ProtocolId::V1_20_50, ProtocolId::LATEST).is_empty());
+        // the packets registered in this tree kept their ids.
+        assert!(Translator::new(ProtocolId::V1_20_0, ProtocolId::LATEST)
+            .ids
+            .is_empty());
+    }
+}
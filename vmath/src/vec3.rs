@@ -11,14 +11,18 @@ pub struct Vec3<T: Copy> {
     pub z: T,
 }
 
-impl bstream::BinaryStream for Vec3<f32> {
-    fn read(&mut self, out: &mut impl io::Read) -> io::Result<()> {
-        self.x = ReadBytesExt::read_f32::<LittleEndian>(out)?;
-        self.y = ReadBytesExt::read_f32::<LittleEndian>(out)?;
-        self.z = ReadBytesExt::read_f32::<LittleEndian>(out)?;
-        Ok(())
+impl bstream::Decode for Vec3<f32> {
+    fn decode(out: &mut impl io::Read) -> bstream::Result<Self> {
+        Ok(Self {
+            x: ReadBytesExt::read_f32::<LittleEndian>(out)?,
+            y: ReadBytesExt::read_f32::<LittleEndian>(out)?,
+            z: ReadBytesExt::read_f32::<LittleEndian>(out)?,
+        })
     }
-    fn write(&self, out: &mut impl io::Write) -> io::Result<()> {
+}
+
+impl bstream::Encode for Vec3<f32> {
+    fn encode(&self, out: &mut impl io::Write) -> bstream::Result<()> {
         byteorder::WriteBytesExt::write_f32::<LittleEndian>(out, self.x)?;
         byteorder::WriteBytesExt::write_f32::<LittleEndian>(out, self.y)?;
         byteorder::WriteBytesExt::write_f32::<LittleEndian>(out, self.z)?;